@@ -25,12 +25,18 @@ pub enum Def {
     /// Function definition, with the NodeIds of the args, the return type, and the NodeIds of any type parameters
     FuncDef(Vec<NodeId>, Type, Vec<NodeId>),
 
+    /// Extern function declaration, like a FuncDef but also recording whether it is variadic
+    ExternFuncDef(Vec<NodeId>, Type, Vec<NodeId>, bool),
+
     /// Function argument definition (maybe this should be replaced with PatDef?)
     FuncArgDef(Type),
 
     /// Struct definition, with a map of fields names to their types and the NodeIds of any type parameters
     StructDef(TreeMap<Name, Type>, Vec<NodeId>),
 
+    /// Union definition, like a StructDef
+    UnionDef(TreeMap<Name, Type>, Vec<NodeId>),
+
     /// Enum definition, with the NodeIds of the variants and any type parameters
     EnumDef(Vec<NodeId>, Vec<NodeId>),
 
@@ -112,7 +118,21 @@ impl Visitor for DefMap {
 
                 self.visit_block(def);
             },
-            StructItem(ref ident, ref fields, ref tps) => {
+            ExternFuncItem(ref ident, ref args, ref t, ref tps, variadic, _) => {
+                let arg_def_ids = args.iter().map(|arg| {
+                    self.table.insert(arg.ident.id, FuncArgDef(arg.argtype.clone()));
+                    arg.ident.id
+                }).collect();
+
+                let tp_def_ids = tps.iter().map(|tp| {
+                    self.table.insert(tp.id, GenericDef);
+                    tp.id
+                }).collect();
+
+                self.table.insert(ident.id, ExternFuncDef(arg_def_ids, t.clone(), tp_def_ids, variadic));
+            },
+            StructItem(ref ident, ref fields, ref tps) |
+            UnionItem(ref ident, ref fields, ref tps) => {
                 let mut field_map = TreeMap::new();
                 for field in fields.iter() {
                     field_map.insert(field.name, field.fldtype.clone());
@@ -123,7 +143,11 @@ impl Visitor for DefMap {
                     tp.id
                 }).collect();
 
-                self.table.insert(ident.id, StructDef(field_map, tp_def_ids));
+                let def = match item.val {
+                    UnionItem(..) => UnionDef(field_map, tp_def_ids),
+                    _ => StructDef(field_map, tp_def_ids),
+                };
+                self.table.insert(ident.id, def);
             },
             EnumItem(ref ident, ref variants, ref tps) => {
                 let variant_def_ids = variants.iter().map(|variant| {
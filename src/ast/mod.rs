@@ -42,6 +42,7 @@ with_id! {
     Ident    => IdentNode,
     Path     => PathNode,
     Module   => ModuleNode,
+    Attr     => AttrNode,
 }
 
 #[deriving(Eq, PartialEq, Clone, Ord, PartialOrd, Show)]
@@ -127,9 +128,10 @@ impl Show for PatNode {
 #[deriving(Eq, PartialEq, Clone)]
 pub enum TypeNode {
     BoolType,
+    StrType,
     UnitType,
     IntType(IntKind),
-    PtrType(Box<Type>),
+    PtrType(Box<Type>, bool), // pointee, volatile
     NamedType(Path),
     FuncType(Vec<Type>, Box<Type>),
     ArrayType(Box<Type>, u64),
@@ -140,9 +142,11 @@ impl Show for TypeNode {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             BoolType                  => write!(f, "bool"),
+            StrType                   => write!(f, "str"),
             UnitType                  => write!(f, "()"),
             IntType(k)                => write!(f, "{}", k),
-            PtrType(ref t)            => write!(f, "*({})", t),
+            PtrType(ref t, false)     => write!(f, "*({})", t),
+            PtrType(ref t, true)      => write!(f, "*volatile ({})", t),
             NamedType(ref p)          => write!(f, "{}", p),
             FuncType(ref d, ref r)    => write!(f, "({} -> {})", d, r),
             ArrayType(ref t, d)       => write!(f, "({})[{}]", t, d),
@@ -219,6 +223,48 @@ impl Show for UnOpNode {
     }
 }
 
+// The atomic intrinsics.
+#[deriving(Eq, PartialEq, Clone)]
+pub enum AtomicOp {
+    LoadOp,
+    StoreOp,
+    CasOp,
+    FetchAddOp,
+    FenceOp,
+}
+
+impl Show for AtomicOp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            LoadOp     => "atomic_load",
+            StoreOp    => "atomic_store",
+            CasOp      => "atomic_cas",
+            FetchAddOp => "atomic_fetch_add",
+            FenceOp    => "atomic_fence",
+        })
+    }
+}
+
+// The memory orderings of atomic operations, as in C11.
+#[deriving(Eq, PartialEq, Clone)]
+pub enum MemOrder {
+    Relaxed,
+    Acquire,
+    Release,
+    SeqCst,
+}
+
+impl Show for MemOrder {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Relaxed => "relaxed",
+            Acquire => "acquire",
+            Release => "release",
+            SeqCst  => "seq_cst",
+        })
+    }
+}
+
 #[deriving(Eq, PartialEq, Clone)]
 pub enum LitNode {
     NumLit(u64, IntKind),
@@ -231,13 +277,40 @@ impl Show for LitNode {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match *self {
             NumLit(i, nt)     => write!(f, "{}{}", i, nt),
-            StringLit(ref s)  => write!(f, "\"{}\"", s),
+            StringLit(ref s)  => write!(f, "\"{}\"", s.as_slice().escape_default()),
             BoolLit(b)        => write!(f, "BoolLit:{}", b),
             NullLit           => write!(f, "null"),
         }
     }
 }
 
+#[deriving(Eq, PartialEq, Clone)]
+pub enum AsmOperandKind {
+    AsmIn,
+    AsmOut,
+    AsmInOut,
+}
+
+// An operand of inline assembly.
+#[deriving(Eq, PartialEq, Clone)]
+pub struct AsmOperand {
+    pub kind: AsmOperandKind,
+    // A GCC constraint, without the = or + that marks outputs.
+    pub constraint: String,
+    pub expr: Expr,
+}
+
+impl Show for AsmOperand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AsmIn    => "in",
+            AsmOut   => "out",
+            AsmInOut => "inout",
+        };
+        write!(f, "{}(\"{}\") {}", kind, self.constraint, self.expr)
+    }
+}
+
 #[deriving(Eq, PartialEq, Clone)]
 pub struct MatchArm {
     pub pat: Pat,
@@ -256,24 +329,48 @@ pub enum ExprNode {
     LitExpr(Lit),
     GroupExpr(Box<Expr>),
     TupleExpr(Vec<Expr>),
+    ArrayExpr(Vec<Expr>),
     PathExpr(Path),
-    StructExpr(Path, Vec<(Name, Expr)>),
+    StructExpr(Path, Vec<(Name, Expr)>, Option<Box<Expr>>),
     BinOpExpr(BinOp, Box<Expr>, Box<Expr>),
     UnOpExpr(UnOp, Box<Expr>),
     IndexExpr(Box<Expr>, Box<Expr>),
     DotExpr(Box<Expr>, Name),
+    TupleIndexExpr(Box<Expr>, u64),
     ArrowExpr(Box<Expr>, Name),
     AssignExpr(Option<BinOp>, Box<Expr>, Box<Expr>),
     CallExpr(Box<Expr>, Vec<Expr>),
     CastExpr(Box<Expr>, Type),
     IfExpr(Box<Expr>, Box<Block>, Box<Block>),
     BlockExpr(Box<Block>),
+    // A block in which the fields of unions can be read.
+    UnsafeExpr(Box<Block>),
     ReturnExpr(Box<Expr>),
     BreakExpr,
     ContinueExpr,
     WhileExpr(Box<Expr>, Box<Block>),
     ForExpr(Box<Expr>, Box<Expr>, Box<Expr>, Box<Block>),
     MatchExpr(Box<Expr>, Vec<MatchArm>),
+    // The condition, the optional message, and the source text of the
+    // condition.
+    AssertExpr(Box<Expr>, Option<Box<Expr>>, String),
+    PanicExpr(Box<Expr>),
+    UnreachableExpr,
+    // The operation, its operands (the pointer comes first), and the
+    // memory ordering.
+    AtomicExpr(AtomicOp, Vec<Expr>, MemOrder),
+    // The template, the operands, and the clobbers.
+    AsmExpr(String, Vec<AsmOperand>, Vec<String>),
+    SizeofExpr(Type),
+    OffsetofExpr(Type, Name),
+    // `new(value)`. The value may be a path that names a type instead, as
+    // in `new(T)`; the resolver works out which.
+    NewExpr(Box<Expr>),
+    // `new(T)`, for types that can't be read as a value, like `new(u32)`.
+    NewTypeExpr(Type),
+    // The element type and the number of elements.
+    NewArrayExpr(Type, Box<Expr>),
+    FreeExpr(Box<Expr>),
 }
 
 impl Show for ExprNode {
@@ -283,21 +380,53 @@ impl Show for ExprNode {
             LitExpr(ref l)                      => write!(f, "{}", l),
             GroupExpr(ref e)                    => write!(f, "({})", e),
             TupleExpr(ref vs)                   => write!(f, "({})", vs),
+            ArrayExpr(ref vs)                   => write!(f, "{}", vs),
             PathExpr(ref p)                     => write!(f, "{}", p),
-            StructExpr(ref p, ref flds)         => write!(f, "{} \\{ {} \\}", p, flds),
+            StructExpr(ref p, ref flds, None)   => write!(f, "{} \\{ {} \\}", p, flds),
+            StructExpr(ref p, ref flds, Some(ref b)) => write!(f, "{} \\{ {} ..{} \\}", p, flds, b),
             BinOpExpr(op, ref l, ref r)         => write!(f, "({}{}{})", l, op, r),
             UnOpExpr(op, ref e)                 => write!(f, "({}{})", op, e),
             IndexExpr(ref e, ref i)             => write!(f, "{}[{}]", e, i),
             DotExpr(ref e, ref fld)             => write!(f, "{}.{}", e, fld),
+            TupleIndexExpr(ref e, i)            => write!(f, "{}.{}", e, i),
             ArrowExpr(ref e, ref fld)           => write!(f, "{}->{}", e, fld),
             AssignExpr(ref op, ref lv, ref rv)  => write!(f, "({}{}={})", lv, op.map_or(String::new(), |op| format!("{}", op)), rv),
             CallExpr(ref e, ref args)           => write!(f, "{}({})", e, args),
             CastExpr(ref e, ref t)              => write!(f, "({} as {})", e, t),
             IfExpr(ref c, ref bt, ref bf)       => write!(f, "if {} \\{\n    {}\\} else \\{\n    {}\\}", c, bt, bf),
             BlockExpr(ref b)                    => write!(f, "{}", b),
+            UnsafeExpr(ref b)                   => write!(f, "unsafe {}", b),
             ReturnExpr(ref e)                   => write!(f, "return {}", e),
             BreakExpr                           => write!(f, "break"),
             ContinueExpr                        => write!(f, "continue"),
+            AssertExpr(ref e, None, _)          => write!(f, "assert({})", e),
+            AssertExpr(ref e, Some(ref m), _)   => write!(f, "assert({}, {})", e, m),
+            PanicExpr(ref m)                    => write!(f, "panic({})", m),
+            UnreachableExpr                     => write!(f, "unreachable()"),
+            AtomicExpr(FenceOp, _, order)       => write!(f, "atomic_fence({})", order),
+            AtomicExpr(op, ref es, order) => {
+                try!(write!(f, "{}(", op));
+                for e in es.iter() {
+                    try!(write!(f, "{}, ", e));
+                }
+                write!(f, "{})", order)
+            }
+            AsmExpr(ref t, ref ops, ref cs) => {
+                try!(write!(f, "asm!(\"{}\"", t.as_slice().escape_default()));
+                for op in ops.iter() {
+                    try!(write!(f, ", {}", op));
+                }
+                for c in cs.iter() {
+                    try!(write!(f, ", clobber(\"{}\")", c));
+                }
+                write!(f, ")")
+            }
+            SizeofExpr(ref t)                   => write!(f, "sizeof({})", t),
+            OffsetofExpr(ref t, ref fld)        => write!(f, "offsetof({}, {})", t, fld),
+            NewExpr(ref e)                      => write!(f, "new({})", e),
+            NewTypeExpr(ref t)                  => write!(f, "new({})", t),
+            NewArrayExpr(ref t, ref n)          => write!(f, "new_array({}, {})", t, n),
+            FreeExpr(ref e)                     => write!(f, "free({})", e),
             WhileExpr(ref e, ref b)             => write!(f, "while {} {}", e, b),
             ForExpr(ref e1, ref e2, ref e3, ref b) => write!(f, "for ({};{};{}) {}", e1, e2, e3, b),
             MatchExpr(ref e, ref items) => {
@@ -383,6 +512,8 @@ impl Show for FuncArg {
 pub struct Variant {
     pub ident: Ident,
     pub args: Vec<Type>,
+    // The tag given with `= N`, if any.
+    pub disc: Option<i64>,
 }
 
 impl Show for Variant {
@@ -391,12 +522,30 @@ impl Show for Variant {
         for ref argtype in self.args.iter() {
             try!(write!(f, "{}, ", argtype));
         }
-        write!(f, ")")
+        try!(write!(f, ")"));
+        match self.disc {
+            Some(n) => write!(f, " = {}", n),
+            None => Ok(()),
+        }
     }
 }
 
+// The tag of each variant of an enum: the one it was given, or one more than
+// the one the variant before it has, starting from 0.
+pub fn discriminants(variants: &Vec<Variant>) -> Vec<i64> {
+    let mut next = 0;
+    variants.iter().map(|variant| {
+        let disc = variant.disc.unwrap_or(next);
+        next = disc + 1;
+        disc
+    }).collect()
+}
+
 #[deriving(Eq, PartialEq, Clone)]
 pub struct Field {
+    // Fields aren't WithIds, but they can have attributes, which are kept
+    // by this id.
+    pub id:      NodeId,
     pub name:    Name,
     pub fldtype: Type,
 }
@@ -407,10 +556,42 @@ impl Show for Field {
     }
 }
 
+// An attribute, like `#[packed]`, `#[align(8)]` or `#[doc = "..."]`. The
+// parser keeps these to one side, by the NodeId of what they're on.
+#[deriving(Eq, PartialEq, Clone)]
+pub enum AttrNode {
+    WordAttr(String),
+    ListAttr(String, Vec<String>),
+    ValueAttr(String, String),
+}
+
+impl AttrNode {
+    pub fn name<'a>(&'a self) -> &'a str {
+        match *self {
+            WordAttr(ref name) | ListAttr(ref name, _) | ValueAttr(ref name, _) =>
+                name.as_slice(),
+        }
+    }
+}
+
+impl Show for AttrNode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            WordAttr(ref name) => write!(f, "#[{}]", name),
+            ListAttr(ref name, ref args) => write!(f, "#[{}({})]", name, args.connect(", ")),
+            ValueAttr(ref name, ref value) =>
+                write!(f, "#[{} = \"{}\"]", name, value.as_slice().escape_default()),
+        }
+    }
+}
+
 #[deriving(Eq, PartialEq, Clone)]
 pub enum ItemNode {
     FuncItem(Ident, Vec<FuncArg>, Type, Block, Vec<Ident>),
+    // name, args, return type, type params, variadic, link name
+    ExternFuncItem(Ident, Vec<FuncArg>, Type, Vec<Ident>, bool, Option<String>),
     StructItem(Ident, Vec<Field>, Vec<Ident>),
+    UnionItem(Ident, Vec<Field>, Vec<Ident>),
     EnumItem(Ident, Vec<Variant>, Vec<Ident>),
     ModItem(Ident, Module),
     StaticItem(Ident, Option<Type>, Option<Expr>),
@@ -426,8 +607,23 @@ impl Show for ItemNode {
                 }
                 write!(f, "({}) -> {} {}", args, t, def)
             },
-            StructItem(ref id, ref fields, ref tps) => {
-                try!(write!(f, "struct {}", id));
+            ExternFuncItem(ref id, ref args, ref t, ref tps, variadic, ref link_name) => {
+                try!(write!(f, "extern fn {}", id));
+                if tps.len() > 0 {
+                    try!(write!(f, "<{}>", tps));
+                }
+                try!(write!(f, "({}{}) -> {}", args, if variadic { ", ..." } else { "" }, t));
+                for name in link_name.iter() {
+                    try!(write!(f, " as {}", name));
+                }
+                write!(f, ";")
+            },
+            StructItem(ref id, ref fields, ref tps) | UnionItem(ref id, ref fields, ref tps) => {
+                let keyword = match *self {
+                    UnionItem(..) => "union",
+                    _ => "struct",
+                };
+                try!(write!(f, "{} {}", keyword, id));
                 if tps.len() > 0 {
                     try!(write!(f, "<{}>", tps));
                 }
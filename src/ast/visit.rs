@@ -15,9 +15,13 @@ pub trait Visitor {
     fn visit_ident(&mut self, ident: &Ident) { walk_ident(self, ident) }
     fn visit_path(&mut self, path: &Path) { walk_path(self, path) }
     fn visit_module(&mut self, module: &Module) { walk_module(self, module) }
+    // Called with the id of each node that can have attributes, for
+    // visitors that look them up in the session.
+    fn visit_attrs(&mut self, _: &NodeId) {}
 }
 
 pub fn walk_item<T: Visitor>(visitor: &mut T, item: &Item) {
+    visitor.visit_attrs(&item.id);
     match item.val {
         FuncItem(ref id, ref args, ref t, ref def, ref tps) => {
             visitor.visit_ident(id);
@@ -26,7 +30,13 @@ pub fn walk_item<T: Visitor>(visitor: &mut T, item: &Item) {
             visitor.visit_block(def);
             for id in tps.iter() { visitor.visit_ident(id); }
         },
-        StructItem(ref id, ref fields, ref tps) => {
+        ExternFuncItem(ref id, ref args, ref t, ref tps, _, _) => {
+            visitor.visit_ident(id);
+            for arg in args.iter() { visitor.visit_func_arg(arg); }
+            visitor.visit_type(t);
+            for id in tps.iter() { visitor.visit_ident(id); }
+        },
+        StructItem(ref id, ref fields, ref tps) | UnionItem(ref id, ref fields, ref tps) => {
             visitor.visit_ident(id);
             for field in fields.iter() { visitor.visit_struct_field(field); }
             for id in tps.iter() { visitor.visit_ident(id); }
@@ -50,7 +60,7 @@ pub fn walk_item<T: Visitor>(visitor: &mut T, item: &Item) {
 
 pub fn walk_type<T: Visitor>(visitor: &mut T, t: &Type) {
     match t.val {
-        PtrType(ref p) => {
+        PtrType(ref p, _) => {
             visitor.visit_type(*p);
         }
         NamedType(ref p) => {
@@ -66,7 +76,7 @@ pub fn walk_type<T: Visitor>(visitor: &mut T, t: &Type) {
         TupleType(ref ts) => {
             for t in ts.iter() { visitor.visit_type(t); }
         }
-        BoolType | UnitType | IntType(..) => {}
+        BoolType | StrType | UnitType | IntType(..) => {}
     }
 }
 
@@ -100,6 +110,7 @@ pub fn walk_pat<T: Visitor>(visitor: &mut T, pat: &Pat) {
 }
 
 pub fn walk_variant<T: Visitor>(visitor: &mut T, variant: &Variant) {
+    visitor.visit_attrs(&variant.ident.id);
     visitor.visit_ident(&variant.ident);
     for arg in variant.args.iter() {
         visitor.visit_type(arg);
@@ -107,10 +118,12 @@ pub fn walk_variant<T: Visitor>(visitor: &mut T, variant: &Variant) {
 }
 
 pub fn walk_struct_field<T: Visitor>(visitor: &mut T, field: &Field) {
+    visitor.visit_attrs(&field.id);
     visitor.visit_type(&field.fldtype);
 }
 
 pub fn walk_func_arg<T: Visitor>(visitor: &mut T, arg: &FuncArg) {
+    visitor.visit_attrs(&arg.ident.id);
     visitor.visit_ident(&arg.ident);
     visitor.visit_type(&arg.argtype);
 }
@@ -118,10 +131,15 @@ pub fn walk_func_arg<T: Visitor>(visitor: &mut T, arg: &FuncArg) {
 pub fn walk_block<T: Visitor>(visitor: &mut T, block: &Block) {
     for item in block.items.iter() { visitor.visit_item(item); }
     for stmt in block.stmts.iter() { visitor.visit_stmt(stmt); }
-    for expr in block.expr.iter()  { visitor.visit_expr(expr); }
+    for expr in block.expr.iter() {
+        // A trailing expression keeps the attributes of its statement.
+        visitor.visit_attrs(&expr.id);
+        visitor.visit_expr(expr);
+    }
 }
 
 pub fn walk_stmt<T: Visitor>(visitor: &mut T, stmt: &Stmt) {
+    visitor.visit_attrs(&stmt.id);
     match stmt.val {
         LetStmt(ref pat, ref e) => {
             visitor.visit_pat(pat);
@@ -142,7 +160,7 @@ pub fn walk_expr<T: Visitor>(visitor: &mut T, expr: &Expr) {
         LitExpr(ref l) => {
             visitor.visit_lit(l);
         }
-        TupleExpr(ref es) => {
+        TupleExpr(ref es) | ArrayExpr(ref es) => {
             for e in es.iter() { visitor.visit_expr(e); }
         }
         GroupExpr(ref e) => {
@@ -151,11 +169,14 @@ pub fn walk_expr<T: Visitor>(visitor: &mut T, expr: &Expr) {
         PathExpr(ref p) => {
             visitor.visit_path(p);
         }
-        StructExpr(ref p, ref flds) => {
+        StructExpr(ref p, ref flds, ref base) => {
             visitor.visit_path(p);
             for fld in flds.iter() {
                 visitor.visit_expr(fld.ref1());
             }
+            for base in base.iter() {
+                visitor.visit_expr(*base);
+            }
         }
         BinOpExpr(_, ref l, ref r) => {
             visitor.visit_expr(*l);
@@ -171,6 +192,9 @@ pub fn walk_expr<T: Visitor>(visitor: &mut T, expr: &Expr) {
         DotExpr(ref e, _) => {
             visitor.visit_expr(*e);
         }
+        TupleIndexExpr(ref e, _) => {
+            visitor.visit_expr(*e);
+        }
         ArrowExpr(ref e, _) => {
             visitor.visit_expr(*e);
         }
@@ -191,7 +215,7 @@ pub fn walk_expr<T: Visitor>(visitor: &mut T, expr: &Expr) {
             visitor.visit_block(*tb);
             visitor.visit_block(*fb);
         }
-        BlockExpr(ref b) => {
+        BlockExpr(ref b) | UnsafeExpr(ref b) => {
             visitor.visit_block(*b);
         }
         ReturnExpr(ref e) => {
@@ -199,6 +223,30 @@ pub fn walk_expr<T: Visitor>(visitor: &mut T, expr: &Expr) {
         }
         BreakExpr => {}
         ContinueExpr => {}
+        AssertExpr(ref e, ref msg, _) => {
+            visitor.visit_expr(*e);
+            for msg in msg.iter() { visitor.visit_expr(*msg); }
+        }
+        PanicExpr(ref msg) => {
+            visitor.visit_expr(*msg);
+        }
+        UnreachableExpr => {}
+        AtomicExpr(_, ref es, _) => {
+            for e in es.iter() { visitor.visit_expr(e); }
+        }
+        AsmExpr(_, ref ops, _) => {
+            for op in ops.iter() { visitor.visit_expr(&op.expr); }
+        }
+        SizeofExpr(ref t) | OffsetofExpr(ref t, _) | NewTypeExpr(ref t) => {
+            visitor.visit_type(t);
+        }
+        NewExpr(ref e) | FreeExpr(ref e) => {
+            visitor.visit_expr(*e);
+        }
+        NewArrayExpr(ref t, ref n) => {
+            visitor.visit_type(t);
+            visitor.visit_expr(*n);
+        }
         WhileExpr(ref e, ref b) => {
             visitor.visit_expr(*e);
             visitor.visit_block(*b);
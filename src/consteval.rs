@@ -0,0 +1,349 @@
+/* Evaluates the initializers of statics at compile time. C only allows
+ * constant expressions there, so the initializer is worked out here and the
+ * target writes out the value. Besides literals and arithmetic, a constant
+ * can be built from struct, tuple and array literals, enum variants, the
+ * values of other statics, and the addresses of statics and functions.
+ */
+
+use std::cell::RefCell;
+use std::collections::TreeMap;
+
+use ast::*;
+use ast::defmap::*;
+use session::Session;
+use typechecker::{Ty, Typemap, Concrete};
+use typechecker::{BoolTy, GenericIntTy, IntTy, UintTy, PtrTy, EnumTy, BoundTy};
+use util::{IntKind, Name, GenericInt, SignedInt, UnsignedInt, OverflowMode};
+use values::{int_op, int_cast, int_value, relation_op};
+
+/// A value known at compile time.
+#[deriving(Clone, Show)]
+pub enum Const {
+    /// An integer (of the type of the expression it came from), a bool, a
+    /// string, or null.
+    LitConst(LitNode),
+    TupleConst(Vec<Const>),
+    ArrayConst(Vec<Const>),
+    StructConst(Vec<(Name, Const)>),
+    /// An enum variant, by the NodeId of its ident, its arguments, and the
+    /// enum type, whose layout depends on the type arguments.
+    VariantConst(NodeId, Vec<Const>, Ty),
+    /// The address of a static, by the NodeId of its ident.
+    AddrConst(NodeId),
+    /// A function, by the NodeId of its ident.
+    FuncConst(NodeId),
+    /// A constant cast to a pointer type.
+    PtrCastConst(Box<Const>, Ty),
+}
+
+pub struct ConstEvaluator<'a> {
+    session: &'a Session,
+    typemap: &'a Typemap,
+    mode: OverflowMode,
+    // The initializer of every static, by the NodeId of its ident.
+    statics: TreeMap<NodeId, Option<&'a Expr>>,
+    // The tag of every enum variant, by the NodeId of its ident.
+    discs: TreeMap<NodeId, i64>,
+    // The statics whose initializers are being evaluated, to catch ones
+    // that depend on their own values.
+    evaluating: RefCell<Vec<NodeId>>,
+}
+
+impl<'a> ConstEvaluator<'a> {
+    pub fn new(session: &'a Session, typemap: &'a Typemap, mode: OverflowMode,
+               items: &'a Vec<Item>) -> ConstEvaluator<'a> {
+        let mut statics = TreeMap::new();
+        let mut discs = TreeMap::new();
+        for item in items.iter() {
+            match item.val {
+                StaticItem(ref id, _, ref e) => {
+                    statics.insert(id.id.clone(), e.as_ref());
+                }
+                EnumItem(_, ref variants, _) => {
+                    for (variant, disc) in variants.iter().zip(discriminants(variants).move_iter()) {
+                        discs.insert(variant.ident.id, disc);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        ConstEvaluator {
+            session: session,
+            typemap: typemap,
+            mode: mode,
+            statics: statics,
+            discs: discs,
+            evaluating: RefCell::new(vec!()),
+        }
+    }
+
+    fn error(&self, expr: &Expr, msg: &str) -> ! {
+        let file = self.session.parser.filename_of(&expr.id);
+        let pos = self.session.parser.span_of(&expr.id).get_begin();
+        fail!("{}:{}:{}: {}", self.session.interner.name_to_str(&file),
+              pos.row + 1, pos.col + 1, msg)
+    }
+
+    fn resolve(&self, t: &Ty) -> Ty {
+        match *t {
+            BoundTy(bid) => match *self.typemap.find_bounds(bid) {
+                Concrete(ref t) => self.resolve(t),
+                // An integer whose type was never pinned down.
+                _ => GenericIntTy,
+            },
+            ref t => t.clone(),
+        }
+    }
+
+    fn ty(&self, expr: &Expr) -> Ty {
+        self.resolve(self.typemap.types.get(&expr.id.to_uint()))
+    }
+
+    fn int_kind(&self, expr: &Expr) -> IntKind {
+        match self.ty(expr) {
+            IntTy(w) => SignedInt(w),
+            UintTy(w) => UnsignedInt(w),
+            _ => GenericInt,
+        }
+    }
+
+    fn eval_int(&self, expr: &Expr) -> LitNode {
+        match self.eval(expr) {
+            LitConst(lit @ NumLit(..)) => int_cast(&lit, self.int_kind(expr)),
+            _ => self.error(expr, "expected a constant integer"),
+        }
+    }
+
+    fn eval_bool(&self, expr: &Expr) -> bool {
+        match self.eval(expr) {
+            LitConst(BoolLit(b)) => b,
+            _ => self.error(expr, "expected a constant bool"),
+        }
+    }
+
+    /// The value of the initializer of the static whose ident is `id`.
+    pub fn eval_static(&self, id: NodeId) -> Option<Const> {
+        let expr = match self.statics.find(&id) {
+            Some(&Some(expr)) => expr,
+            _ => return None,
+        };
+
+        if self.evaluating.borrow().contains(&id) {
+            self.error(expr, "this static's initializer depends on its own value");
+        }
+        self.evaluating.borrow_mut().push(id);
+        let value = self.eval(expr);
+        self.evaluating.borrow_mut().pop();
+        Some(value)
+    }
+
+    /// The value of `expr`, or an error if it isn't constant.
+    pub fn eval(&self, expr: &Expr) -> Const {
+        match expr.val {
+            LitExpr(ref lit) => match lit.val {
+                NumLit(..) => LitConst(int_cast(&lit.val, self.int_kind(expr))),
+                ref lit => LitConst(lit.clone()),
+            },
+            GroupExpr(ref e) => self.eval(*e),
+            TupleExpr(ref es) => TupleConst(es.iter().map(|e| self.eval(e)).collect()),
+            ArrayExpr(ref es) => ArrayConst(es.iter().map(|e| self.eval(e)).collect()),
+            StructExpr(_, ref flds, None) => {
+                StructConst(flds.iter().map(|&(name, ref e)| (name, self.eval(e))).collect())
+            }
+            PathExpr(ref path) => {
+                let did = self.session.resolver.def_from_path(path);
+                match self.session.defmap.find(&did) {
+                    Some(&VariantDef(..)) => VariantConst(did, vec!(), self.ty(expr)),
+                    Some(&FuncDef(_, _, ref tps)) |
+                    Some(&ExternFuncDef(_, _, ref tps, _)) if tps.is_empty() => FuncConst(did),
+                    _ if self.statics.contains_key(&did) => {
+                        match self.eval_static(did) {
+                            Some(value) => value,
+                            None => self.error(expr, "a static with no initializer has no constant value"),
+                        }
+                    }
+                    _ => self.error(expr, "not a constant"),
+                }
+            }
+            CallExpr(ref f, ref args) => {
+                let did = match f.val {
+                    PathExpr(ref path) => self.session.resolver.def_from_path(path),
+                    _ => self.error(expr, "function calls are not constant"),
+                };
+                match self.session.defmap.find(&did) {
+                    Some(&VariantDef(..)) => {
+                        VariantConst(did, args.iter().map(|e| self.eval(e)).collect(), self.ty(expr))
+                    }
+                    _ => self.error(expr, "function calls are not constant"),
+                }
+            }
+            UnOpExpr(ref op, ref e) => match op.val {
+                AddrOf => {
+                    let did = match e.val {
+                        PathExpr(ref path) => self.session.resolver.def_from_path(path),
+                        _ => self.error(expr, "only the addresses of statics are constant"),
+                    };
+                    if !self.statics.contains_key(&did) {
+                        self.error(expr, "only the addresses of statics are constant");
+                    }
+                    AddrConst(did)
+                }
+                Negate => {
+                    let n = self.eval_int(*e);
+                    let zero = int_cast(&NumLit(0, GenericInt), self.int_kind(expr));
+                    match int_op(&MinusOp, &zero, &n, self.mode) {
+                        Some(lit) => LitConst(lit),
+                        None => self.error(expr, "integer overflow in a constant"),
+                    }
+                }
+                BitNot => {
+                    let kind = self.int_kind(expr);
+                    let n = self.eval_int(*e);
+                    LitConst(int_op(&BitXorOp, &n, &NumLit(!0, kind), self.mode).unwrap())
+                }
+                LogNot => LitConst(BoolLit(!self.eval_bool(*e))),
+                Deref => self.error(expr, "dereferences are not constant"),
+            },
+            BinOpExpr(ref op, ref l, ref r) => self.eval_binop(expr, &op.val, *l, *r),
+            CastExpr(ref e, _) => {
+                match self.ty(expr) {
+                    PtrTy(..) => match self.eval(*e) {
+                        LitConst(NullLit) => LitConst(NullLit),
+                        LitConst(NumLit(0, _)) => LitConst(NullLit),
+                        value => PtrCastConst(box value, self.ty(expr)),
+                    },
+                    EnumTy(nid, _) => {
+                        let n = int_value(&self.eval_int(*e));
+                        let variants = match *self.session.defmap.find(&nid).take_unwrap() {
+                            EnumDef(ref variants, _) => variants,
+                            _ => unreachable!(),
+                        };
+                        match variants.iter().find(|vid| *self.discs.find(*vid).unwrap() == n) {
+                            Some(vid) => VariantConst(*vid, vec!(), self.ty(expr)),
+                            None => self.error(expr, "invalid enum discriminant"),
+                        }
+                    }
+                    _ => match self.eval(*e) {
+                        VariantConst(did, _, _) => {
+                            let n = *self.discs.find(&did).unwrap();
+                            LitConst(int_cast(&NumLit(n as u64, GenericInt), self.int_kind(expr)))
+                        }
+                        _ => LitConst(int_cast(&self.eval_int(*e), self.int_kind(expr))),
+                    },
+                }
+            }
+            _ => self.error(expr, "not a constant"),
+        }
+    }
+
+    fn eval_binop(&self, expr: &Expr, op: &BinOpNode, l: &Expr, r: &Expr) -> Const {
+        match *op {
+            AndAlsoOp => LitConst(BoolLit(self.eval_bool(l) && self.eval_bool(r))),
+            OrElseOp => LitConst(BoolLit(self.eval_bool(l) || self.eval_bool(r))),
+            EqualsOp | NotEqualsOp if self.ty(l) == BoolTy => {
+                let eq = self.eval_bool(l) == self.eval_bool(r);
+                LitConst(BoolLit(if *op == EqualsOp { eq } else { !eq }))
+            }
+            EqualsOp | NotEqualsOp | LessOp | LessEqOp | GreaterOp | GreaterEqOp => {
+                // Both sides are compared as the type of the left.
+                let kind = self.int_kind(l);
+                let a = self.eval_int(l);
+                let b = int_cast(&self.eval_int(r), kind);
+                LitConst(relation_op(&a, &b, |a, b| match *op {
+                    EqualsOp => a == b,
+                    NotEqualsOp => a != b,
+                    LessOp => a < b,
+                    LessEqOp => a <= b,
+                    GreaterOp => a > b,
+                    _ => a >= b,
+                }))
+            }
+            _ => {
+                let kind = self.int_kind(expr);
+                let a = int_cast(&self.eval_int(l), kind);
+                let b = int_cast(&self.eval_int(r), kind);
+                match int_op(op, &a, &b, self.mode) {
+                    Some(lit) => LitConst(lit),
+                    None => self.error(expr, "integer overflow, division by zero, or an over-wide shift in a constant"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use session::Session;
+    use typechecker::Typechecker;
+    use ast::visit::Visitor;
+    use ast::{NumLit, StaticItem};
+    use util::{UnsignedInt, SignedInt, Width8, Width32, Wrapping, Checked};
+    use super::{ConstEvaluator, LitConst, AddrConst, StructConst, ArrayConst};
+
+    #[test]
+    fn const_eval_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+struct node {
+    next: *node,
+    n: u8[2],
+}
+
+static a: u8 = 200;
+static b: u8 = a + 100;
+static c: i32 = -(1 << 4) / 3;
+static head: node = node { next: &head, n: [b, 3] };
+");
+        let typemap = {
+            let mut tyck = Typechecker::new(&session);
+            tyck.visit_module(&tree);
+            tyck.get_typemap()
+        };
+
+        let ids: Vec<_> = tree.val.items.iter().filter_map(|item| match item.val {
+            StaticItem(ref id, _, _) => Some(id.id.clone()),
+            _ => None,
+        }).collect();
+
+        let consts = ConstEvaluator::new(&session, &typemap, Wrapping, &tree.val.items);
+        match consts.eval_static(ids.get(1).clone()) {
+            Some(LitConst(lit)) => assert_eq!(lit, NumLit(44, UnsignedInt(Width8))),
+            c => fail!("b is {}", c),
+        }
+        match consts.eval_static(ids.get(2).clone()) {
+            Some(LitConst(lit)) => assert_eq!(lit, NumLit(-5i64 as u64 & 0xffffffff, SignedInt(Width32))),
+            c => fail!("c is {}", c),
+        }
+        match consts.eval_static(ids.get(3).clone()) {
+            Some(StructConst(ref flds)) => {
+                match flds.as_slice() {
+                    [(_, AddrConst(ref id)), (_, ArrayConst(ref ns))] => {
+                        assert_eq!(id, ids.get(3));
+                        assert_eq!(ns.len(), 2);
+                    }
+                    _ => fail!("head is {}", flds),
+                }
+            }
+            c => fail!("head is {}", c),
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn checked_const_overflow() {
+        let mut session = Session::new();
+        let tree = session.parse_str("static b: u8 = 200 + 100;");
+        let typemap = {
+            let mut tyck = Typechecker::new(&session);
+            tyck.visit_module(&tree);
+            tyck.get_typemap()
+        };
+        let id = match tree.val.items.get(0).val {
+            StaticItem(ref id, _, _) => id.id.clone(),
+            _ => unreachable!(),
+        };
+
+        ConstEvaluator::new(&session, &typemap, Checked, &tree.val.items).eval_static(id);
+    }
+}
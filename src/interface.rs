@@ -0,0 +1,255 @@
+/* Writes the interface of a module: the parts of it that another program
+ * can use once the two are compiled separately and linked together. That's
+ * every struct, union and enum, so that both sides agree on their layout, and the
+ * exported functions, which become extern declarations. Another program
+ * brings an interface in with `import "file.mh";`.
+ */
+
+use ast::*;
+use session::Session;
+use util::Name;
+
+struct InterfaceWriter<'a> {
+    session: &'a Session,
+    lines: Vec<String>,
+    indent: uint,
+}
+
+pub fn module_interface(session: &Session, module: &Module) -> String {
+    let mut writer = InterfaceWriter {
+        session: session,
+        lines: vec!(),
+        indent: 0,
+    };
+    writer.write_module(module);
+
+    let mut res = String::from_str("// Generated by `mc --emit interface`.\n");
+    for line in writer.lines.iter() {
+        res.push_str(line.as_slice());
+        res.push_char('\n');
+    }
+    res
+}
+
+impl<'a> InterfaceWriter<'a> {
+    fn line(&mut self, s: String) {
+        let indent = " ".repeat(self.indent * 4);
+        self.lines.push(format!("{}{}", indent, s));
+    }
+
+    fn name(&self, name: &Name) -> String {
+        self.session.interner.name_to_str(name).to_string()
+    }
+
+    fn type_params(&self, tps: &Vec<Ident>) -> String {
+        if tps.is_empty() {
+            String::new()
+        } else {
+            let tps: Vec<String> = tps.iter().map(|tp| self.name(&tp.val.name)).collect();
+            format!("<{}>", tps.connect(", "))
+        }
+    }
+
+    fn path(&self, path: &Path) -> String {
+        let elems: Vec<String> = path.val.elems.iter().map(|elem| {
+            let tps = match elem.val.tps {
+                Some(ref tps) if !tps.is_empty() => {
+                    let tps: Vec<String> = tps.iter().map(|t| self.ty(t)).collect();
+                    format!("<{}>", tps.connect(", "))
+                }
+                _ => String::new(),
+            };
+            format!("{}{}", self.name(&elem.val.name), tps)
+        }).collect();
+
+        let prefix = if path.val.global { "::" } else { "" };
+        format!("{}{}", prefix, elems.connect("::"))
+    }
+
+    fn ty(&self, t: &Type) -> String {
+        match t.val {
+            BoolType => String::from_str("bool"),
+            StrType => String::from_str("str"),
+            UnitType => String::from_str("()"),
+            IntType(k) => format!("{}", k),
+            PtrType(ref t, false) => format!("*{}", self.ty(*t)),
+            PtrType(ref t, true) => format!("*volatile {}", self.ty(*t)),
+            NamedType(ref p) => self.path(p),
+            FuncType(ref d, ref r) => {
+                let d: Vec<String> = d.iter().map(|t| self.ty(t)).collect();
+                format!("fn({}) -> {}", d.connect(", "), self.ty(*r))
+            }
+            ArrayType(ref t, n) => {
+                // Pointer and function types would swallow the brackets.
+                match t.val {
+                    PtrType(..) | FuncType(..) => format!("({})[{}]", self.ty(*t), n),
+                    _ => format!("{}[{}]", self.ty(*t), n),
+                }
+            }
+            TupleType(ref ts) => {
+                let ts: Vec<String> = ts.iter().map(|t| self.ty(t)).collect();
+                format!("({})", ts.connect(", "))
+            }
+        }
+    }
+
+    fn write_module(&mut self, module: &Module) {
+        for item in module.val.items.iter() {
+            // What was imported is another module's interface, which a
+            // program that uses both imports for itself.
+            if !self.session.parser.is_imported(&item.id) {
+                self.write_item(item);
+            }
+        }
+    }
+
+    // The layout attributes have to come along too.
+    fn write_attrs(&mut self, item: &Item) {
+        let session = self.session;
+        for attrs in session.parser.attrs_of(&item.id).iter() {
+            for attr in attrs.iter() {
+                self.line(format!("{}", attr));
+            }
+        }
+    }
+
+    fn write_item(&mut self, item: &Item) {
+        match item.val {
+            FuncItem(ref id, ref args, ref t, _, _)
+                if self.session.parser.is_exported(&id.id) => {
+                let args: Vec<String> = args.iter().map(|arg| {
+                    format!("{}: {}", self.name(&arg.ident.val.name), self.ty(&arg.argtype))
+                }).collect();
+                let line = format!("extern fn {}({}) -> {};",
+                                   self.name(&id.val.name), args.connect(", "), self.ty(t));
+                self.line(line);
+            }
+            StructItem(ref id, ref fields, ref tps) | UnionItem(ref id, ref fields, ref tps) => {
+                let keyword = match item.val {
+                    UnionItem(..) => "union",
+                    _ => "struct",
+                };
+                self.write_attrs(item);
+                let line = format!("{} {}{} \\{", keyword, self.name(&id.val.name),
+                                   self.type_params(tps));
+                self.line(line);
+                for field in fields.iter() {
+                    let line = format!("    {}: {},", self.name(&field.name), self.ty(&field.fldtype));
+                    self.line(line);
+                }
+                self.line(String::from_str("}"));
+            }
+            EnumItem(ref id, ref variants, ref tps) => {
+                self.write_attrs(item);
+                let line = format!("enum {}{} \\{", self.name(&id.val.name), self.type_params(tps));
+                self.line(line);
+                for variant in variants.iter() {
+                    let name = self.name(&variant.ident.val.name);
+                    let line = if variant.args.is_empty() {
+                        match variant.disc {
+                            Some(n) => format!("    {} = {},", name, n),
+                            None => format!("    {},", name),
+                        }
+                    } else {
+                        let args: Vec<String> = variant.args.iter().map(|t| self.ty(t)).collect();
+                        format!("    {}({}),", name, args.connect(", "))
+                    };
+                    self.line(line);
+                }
+                self.line(String::from_str("}"));
+            }
+            // The other side has a prelude of its own.
+            ModItem(ref id, _) if self.session.resolver.is_prelude(&id.id) => {}
+            ModItem(ref id, ref module) => {
+                // Leave out modules with nothing to show.
+                let start = self.lines.len();
+                let line = format!("mod {} \\{", self.name(&id.val.name));
+                self.line(line);
+                self.indent += 1;
+                self.write_module(module);
+                self.indent -= 1;
+                if self.lines.len() == start + 1 {
+                    self.lines.pop();
+                } else {
+                    self.line(String::from_str("}"));
+                }
+            }
+            // Other functions and statics aren't visible outside the C file
+            // they're compiled to, and externs are the program's own
+            // business.
+            FuncItem(..) | ExternFuncItem(..) | StaticItem(..) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use session::Session;
+    use super::module_interface;
+
+    #[test]
+    fn interface_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+struct point {
+    x: i32,
+    y: i32,
+}
+
+fn helper(p: point) -> i32 { p.x }
+
+mod shapes {
+    enum shape<T> {
+        Dot(::point),
+        Poly(*::point, T),
+        Empty,
+    }
+
+    pub extern fn area(s: *shape<u32>, scale: (*u8)[2]) -> u32 { 0 }
+}
+
+mod nothing {
+    static n: u32 = 5;
+}
+");
+
+        assert_eq!(module_interface(&session, &tree).as_slice(), "\
+// Generated by `mc --emit interface`.
+struct point {
+    x: i32,
+    y: i32,
+}
+mod shapes {
+    enum shape<T> {
+        Dot(::point),
+        Poly(*::point, T),
+        Empty,
+    }
+    extern fn area(s: *shape<u32>, scale: (*u8)[2]) -> u32;
+}
+");
+    }
+
+    // Run from the top of the tree, as `make run-tests` does.
+    #[test]
+    fn imports_left_out() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r#"
+import "test/point.mh";
+import "test/../test/point.mh";
+
+struct line {
+    a: point,
+    b: point,
+}
+"#);
+
+        assert_eq!(module_interface(&session, &tree).as_slice(), "\
+// Generated by `mc --emit interface`.
+struct line {
+    a: point,
+    b: point,
+}
+");
+    }
+}
@@ -3,41 +3,101 @@
 /// stuff. It should be deleted when the IR code gets to the point where
 /// this is no longer needed.
 ///
-use std::io;
-use lexer::Lexer;
-use parser::Parser;
-use session::Interner;
+use ast::FuncItem;
+use ast::visit::Visitor;
+use session::Session;
+use typechecker::Typechecker;
+use ir::{Op, Assign, VolatilePtrLValue, VolatileLoadRValue};
 use ir::liveness::LivenessAnalyzer;
 use ir::ast_to_intermediate::ASTToIntermediate;
 use ir::constant_fold::ConstantFolder;
 use ir::ssa::ToSSA;
+use util::OverflowMode;
 
-pub fn main() {
-    let buffer = io::BufferedReader::new(io::MemReader::new(
-        //Vec::from_slice("{*(a*5+6*7)=4u16*(5u16+1u16)*foo; b=a+1; c=6+7<2 || a}".as_bytes())
-        //Vec::from_slice("{ while(1<2) { x = x + 1; x = 5; z = x + x; x = z; } }".as_bytes())
-        //Vec::from_slice("{ a += 1; *(b+3) += 1; }".as_bytes())
-        Vec::from_slice("{ r = 1; while (e>0) { if e%2 != 0 { r = r * b; } b = b * b; e = e / 2; }; return r; }".as_bytes())
-        ));
-    let mut parser = Parser::new();
-    let mut interner = Interner::new();
-    let lexer = Lexer::new("<stdin>", buffer);
-
-    let ast = parser.parse_with(lexer, &mut interner, |p| p.parse_expr());
-    let mut conv = ASTToIntermediate::new(&mut interner);
-    let (mut ops, var) = conv.convert_expr(&ast);
-
-    print!("{}\n", ast);
-    print!("{}\n", (&ops, var));
-
-    ToSSA::to_ssa(&mut ops);
-    print!("{}\n", ops);
-
-    ConstantFolder::fold(&mut ops);
-    print!("{}\n", ops);
-
-    let opinfo = LivenessAnalyzer::analyze(&ops);
-    for z in opinfo.iter().zip(ops.iter()) {
-        print!("{}", z);
+static PROGRAM: &'static str = r"
+fn pow(b: u32, e: u32, r: u32) -> u32 {
+    r = 1;
+    while e > 0 {
+        if e % 2 != 0 {
+            r = r * b;
+        }
+        b = b * b;
+        e = e / 2;
+    }
+    r
+}
+
+fn poke(reg: *volatile u32, x: u32) -> u32 {
+    x = 6 * 7;
+    *reg = x;
+    x = *reg;
+    *reg = x + 1;
+    x
+}
+";
+
+// The number of volatile loads and stores in `ops`.
+fn count_volatile(ops: &Vec<Op>) -> (uint, uint) {
+    let mut loads = 0;
+    let mut stores = 0;
+    for op in ops.iter() {
+        match *op {
+            Assign(ref lv, ref rv) => {
+                match *lv {
+                    VolatilePtrLValue(..) => stores += 1,
+                    _ => {}
+                }
+                match *rv {
+                    VolatileLoadRValue(..) => loads += 1,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    (loads, stores)
+}
+
+pub fn main(mode: OverflowMode) {
+    let mut session = Session::new();
+    let module = session.parse_str(PROGRAM);
+    // Without the types, no pointer would be taken to be volatile.
+    let typemap = {
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&module);
+        tyck.get_typemap()
+    };
+
+    for item in module.val.items.iter() {
+        let (name, body) = match item.val {
+            FuncItem(ref id, _, _, ref body, _) =>
+                (String::from_str(session.interner.name_to_str(&id.val.name)), body),
+            _ => continue,
+        };
+        let mut conv = ASTToIntermediate::with_typemap(&mut session.interner, &typemap);
+        let (mut ops, var) = conv.convert_block(body);
+
+        print!("{}\n", name);
+        print!("{}\n", (&ops, var));
+        let before = count_volatile(&ops);
+
+        ToSSA::to_ssa(&mut ops);
+        print!("{}\n", ops);
+
+        ConstantFolder::fold(&mut ops, mode);
+        print!("{}\n", ops);
+
+        // However much is known about the values, every volatile load and
+        // store has to stay.
+        let after = count_volatile(&ops);
+        assert_eq!(before, after);
+        if name.as_slice() == "poke" {
+            assert_eq!(after, (1, 2));
+        }
+
+        let opinfo = LivenessAnalyzer::analyze(&ops);
+        for z in opinfo.iter().zip(ops.iter()) {
+            print!("{}", z);
+        }
     }
 }
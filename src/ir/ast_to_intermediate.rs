@@ -1,4 +1,5 @@
 use session::Interner;
+use typechecker::Typemap;
 
 use ast::*;
 use ir::*;
@@ -8,13 +9,29 @@ pub struct ASTToIntermediate<'a> {
     var_count: uint,
     label_count: uint,
     interner: &'a mut Interner,
+    // The types of the expressions, if they've been checked. Without them,
+    // no pointer is taken to be volatile.
+    typemap: Option<&'a Typemap>,
 }
 
 impl<'a> ASTToIntermediate<'a> {
     pub fn new(interner: &'a mut Interner) -> ASTToIntermediate<'a> {
         ASTToIntermediate { var_count: 0,
                             label_count: 0,
-                            interner: interner }
+                            interner: interner,
+                            typemap: None }
+    }
+
+    pub fn with_typemap(interner: &'a mut Interner,
+                        typemap: &'a Typemap) -> ASTToIntermediate<'a> {
+        ASTToIntermediate { var_count: 0,
+                            label_count: 0,
+                            interner: interner,
+                            typemap: Some(typemap) }
+    }
+
+    fn is_volatile_ptr(&self, expr: &Expr) -> bool {
+        self.typemap.map_or(false, |t| t.is_volatile_ptr(&expr.id))
     }
 
     fn gen_temp(&mut self) -> Var {
@@ -121,6 +138,16 @@ impl<'a> ASTToIntermediate<'a> {
                         res.push_all_move(insts);
                         let res_var = self.gen_temp();
                         match lhs_op.val {
+                            Deref if self.is_volatile_ptr(*e) => {
+                                (res_var.clone(),
+                                 vec!(Assign(VarLValue(res_var.clone()),
+                                             VolatileLoadRValue(
+                                                 Variable(var.clone())
+                                                 )
+                                             )
+                                      ),
+                                 VolatilePtrLValue(var.clone()))
+                            },
                             Deref => {
                                 (res_var.clone(),
                                  vec!(Assign(VarLValue(res_var.clone()),
@@ -163,7 +190,18 @@ impl<'a> ASTToIntermediate<'a> {
 
                 (res, final_var)
             }
-            BlockExpr(ref b) => self.convert_block(*b),
+            UnOpExpr(ref op, ref e) => {
+                let (mut insts, var) = self.convert_expr(*e);
+                let res_var = self.gen_temp();
+                let rv = match op.val {
+                    Deref if self.is_volatile_ptr(*e) =>
+                        VolatileLoadRValue(Variable(var)),
+                    _ => UnOpRValue(op.val.clone(), Variable(var)),
+                };
+                insts.push(Assign(VarLValue(res_var.clone()), rv));
+                (insts, res_var)
+            }
+            BlockExpr(ref b) | UnsafeExpr(ref b) => self.convert_block(*b),
             IfExpr(ref e, ref b1, ref b2) => {
                 let (mut insts, if_var) = self.convert_expr(*e);
                 let (b1_insts, b1_var) = self.convert_block(*b1);
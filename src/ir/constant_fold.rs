@@ -5,10 +5,11 @@ use ir::*;
 use ir::util::subst;
 use ast::*;
 use values::*;
+use util::{OverflowMode, Checked};
 
 pub struct ConstantFolder;
 
-fn fold(op: &BinOpNode, e1: &RValueElem, e2: &RValueElem) ->
+fn fold(op: &BinOpNode, e1: &RValueElem, e2: &RValueElem, mode: OverflowMode) ->
     Option<LitNode>
 {
     let lit1 = match *e1 {
@@ -21,9 +22,17 @@ fn fold(op: &BinOpNode, e1: &RValueElem, e2: &RValueElem) ->
     };
 
     match *op {
-        PlusOp => Some(lit1+lit2),
-        TimesOp => Some(lit1*lit2),
-        DivideOp => Some(lit1/lit2),
+        // An operation that's undefined is left alone. In checked mode it,
+        // or one that overflows, would trap every time it ran, so it's an
+        // error.
+        PlusOp | MinusOp | TimesOp | DivideOp | ModOp |
+        BitAndOp | BitOrOp | BitXorOp | LeftShiftOp | RightShiftOp =>
+            match int_op(op, &lit1, &lit2, mode) {
+                None if mode == Checked =>
+                    fail!("Integer overflow, division by zero, or an over-wide shift in a constant: {} {} {}",
+                          lit1, op, lit2),
+                lit => lit,
+            },
         OrElseOp => Some(generic_op(&lit1, &lit2, |_,_| fail!(),
                                     |x, y| x||y)),
         LessOp => Some(relation_op(&lit1, &lit2, |x, y| x < y)),
@@ -35,7 +44,8 @@ fn fold(op: &BinOpNode, e1: &RValueElem, e2: &RValueElem) ->
     }
 }
 
-fn constant_fold_once(ops: &mut Vec<Op>, vars_to_avoid: &TreeSet<Var>) -> bool {
+fn constant_fold_once(ops: &mut Vec<Op>, vars_to_avoid: &TreeSet<Var>,
+                      mode: OverflowMode) -> bool {
     let mut changes = vec!();
 
     for op in ops.iter() {
@@ -54,7 +64,7 @@ fn constant_fold_once(ops: &mut Vec<Op>, vars_to_avoid: &TreeSet<Var>) -> bool {
                                 }
                             },
                             BinOpRValue(ref op, ref v1, ref v2) => {
-                                match fold(op, v1, v2) {
+                                match fold(op, v1, v2, mode) {
                                     Some(ref c) => {
                                         changes.push((v.clone(),
                                                       c.clone()));
@@ -65,6 +75,9 @@ fn constant_fold_once(ops: &mut Vec<Op>, vars_to_avoid: &TreeSet<Var>) -> bool {
                             UnOpRValue(..) => {
                                 // TODO: implement this.
                             }
+                            // The value of a volatile load is never known
+                            // ahead of time.
+                            VolatileLoadRValue(..) => {}
                         },
                     _ => {}
                 },
@@ -85,10 +98,11 @@ fn constant_fold_once(ops: &mut Vec<Op>, vars_to_avoid: &TreeSet<Var>) -> bool {
 
 impl ConstantFolder {
 
-    pub fn fold(ops: &mut Vec<Op>) {
+    pub fn fold(ops: &mut Vec<Op>, mode: OverflowMode) {
         // There are certain variables we are prohibited from substituting.
         // Those include any that appear in labels/gotos, as well as any
-        // that is dereferenced as part of the left hand side of an assignment.
+        // that is dereferenced as part of the left hand side of an assignment,
+        // volatile or not.
         let mut vars_to_avoid = TreeSet::<Var>::new();
         for op in ops.iter() {
             match *op {
@@ -101,7 +115,7 @@ impl ConstantFolder {
                 },
                 Assign(ref lhs, _) => {
                     match *lhs {
-                        PtrLValue(ref var) => {
+                        PtrLValue(ref var) | VolatilePtrLValue(ref var) => {
                             vars_to_avoid.insert(var.clone());
                         },
                         _ => {},
@@ -111,6 +125,6 @@ impl ConstantFolder {
             }
         }
         print!("avoid: {}\n", vars_to_avoid);
-        while constant_fold_once(ops, &vars_to_avoid) {}
+        while constant_fold_once(ops, &vars_to_avoid, mode) {}
     }
 }
@@ -18,6 +18,8 @@ fn seed(ops: &Vec<Op>, opinfo: &mut Vec<OpInfo>) {
                 match *lv {
                     VarLValue(ref v) =>
                     { opinfo.def.insert(v.clone()); },
+                    VolatilePtrLValue(ref v) =>
+                    { opinfo.used.insert(v.clone()); },
                     _ => {},
                 };
                 match *rv {
@@ -33,7 +35,7 @@ fn seed(ops: &Vec<Op>, opinfo: &mut Vec<OpInfo>) {
                             _ => {},
                         };
                     },
-                    UnOpRValue(_, ref v1) => {
+                    UnOpRValue(_, ref v1) | VolatileLoadRValue(ref v1) => {
                         match *v1 {
                             Variable(ref w1) =>
                             { opinfo.used.insert(w1.clone()); },
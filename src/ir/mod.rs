@@ -33,6 +33,9 @@ pub enum LValue {
     VarLValue(Var),
     // Store into the location pointed to by Var.
     PtrLValue(Var),
+    // Store into the location pointed to by Var, which is volatile. The
+    // store must not be removed, or moved past another volatile access.
+    VolatilePtrLValue(Var),
 }
 
 impl Show for LValue {
@@ -40,6 +43,7 @@ impl Show for LValue {
         match *self {
             VarLValue(ref v) => write!(f, "{}", v),
             PtrLValue(ref v) => write!(f, "*{}", v),
+            VolatilePtrLValue(ref v) => write!(f, "volatile *{}", v),
         }
     }
 }
@@ -68,6 +72,9 @@ pub enum RValue {
     UnOpRValue(UnOpNode, RValueElem),
     // An RValueElem (variable or constant) itself.
     DirectRValue(RValueElem),
+    // A load through a volatile pointer. Like a volatile store, it always
+    // happens, and in the order it was written.
+    VolatileLoadRValue(RValueElem),
 }
 
 impl Show for RValue {
@@ -86,6 +93,8 @@ impl Show for RValue {
                        format!("{}", v1)),
             DirectRValue(ref d) => write!(f, "{: >12}",
                                          format!("{}", d)),
+            VolatileLoadRValue(ref d) => write!(f, "volatile * {: >12}",
+                                               format!("{}", d)),
         }
     }
 }
@@ -36,7 +36,8 @@ fn ssa_rvalue(generations: &mut TreeMap<Name, uint>, rv: &mut RValue) {
             ssa_rvalelem(generations, lhs);
             ssa_rvalelem(generations, rhs);
         },
-        UnOpRValue(_, ref mut rhs) => {
+        UnOpRValue(_, ref mut rhs) |
+        VolatileLoadRValue(ref mut rhs) => {
             ssa_rvalelem(generations, rhs);
         }
     }
@@ -288,6 +289,10 @@ impl ToSSA {
                     match *lv {
                         VarLValue(ref mut var) | PtrLValue(ref mut var) =>
                             var.generation = next_gen(gens, var.name),
+                        // The pointer is only read, so this is the same
+                        // generation that the store goes through.
+                        VolatilePtrLValue(ref mut var) =>
+                            var.generation = gen_of(gens, var.name),
                     }
                 },
                 Label(_, ref mut vars) => {
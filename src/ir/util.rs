@@ -26,6 +26,10 @@ fn substituted_rvalue(rv: &RValue,
             DirectRValue(if wrapped_var == *r
                          { new.clone() }
                          else { (*r).clone() }),
+        VolatileLoadRValue(ref r) =>
+            VolatileLoadRValue(if wrapped_var == *r
+                               { new.clone() }
+                               else { (*r).clone() }),
     }
 }
 
@@ -51,6 +55,13 @@ fn sub_vars(vars: &TreeSet<Var>, orig_var: &Var,
     new_vars
 }
 
+fn is_volatile_load(rv: &RValue) -> bool {
+    match *rv {
+        VolatileLoadRValue(..) => true,
+        _ => false,
+    }
+}
+
 // TODO: we do a lot of cloning/replacing. Surely we can improve that?
 pub fn subst(ops: &mut Vec<Op>,
              orig_var: &Var,
@@ -61,7 +72,9 @@ pub fn subst(ops: &mut Vec<Op>,
         let temp = match *op {
             Assign(ref x, ref rv) =>
                 match *x {
-                    VarLValue(ref v) if v == orig_var =>
+                    // A volatile load has to happen even if its result
+                    // is known.
+                    VarLValue(ref v) if v == orig_var && !is_volatile_load(rv) =>
                         Nop,
                     _ =>
                         Assign(x.clone(),
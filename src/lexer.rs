@@ -30,9 +30,11 @@ pub enum Token {
     False,
     IntTypeTok(IntKind),
     Bool,
+    Str,
     While,
     For,
     Struct,
+    Union,
     Enum,
     Match,
     Mod,
@@ -40,7 +42,25 @@ pub enum Token {
     Break,
     Continue,
     Static,
+    Unsafe,
     Volatile,
+    Assert,
+    Panic,
+    Unreachable,
+    AtomicLoad,
+    AtomicStore,
+    AtomicCas,
+    AtomicFetchAdd,
+    AtomicFence,
+    Asm,
+    Sizeof,
+    Offsetof,
+    New,
+    NewArray,
+    Free,
+    Extern,
+    Pub,
+    Import,
 
     // Symbols
     LParen,
@@ -77,7 +97,11 @@ pub enum Token {
     DoubleArrow,
     Comma,
     QuestionMark,
+    Hash,
+    HashBang,
     Period,
+    DotDot,
+    DotDotDot,
     Underscore,
     PlusEq,
     MinusEq,
@@ -105,11 +129,12 @@ pub enum Token {
 }
 
 /// A token together with a Span, to keep track of where in the source file
-/// it was.
+/// it was, and the text it was made from.
 #[deriving(Show, Eq, PartialEq)]
 pub struct SourceToken {
     pub tok: Token,
     pub sp: Span,
+    pub text: String,
 }
 
 /// A single rule for the lexer. This includes a `matcher`, which matches
@@ -282,6 +307,7 @@ impl<T: Buffer> Lexer<T> {
             While        => "while",
             For          => "for",
             Struct       => "struct",
+            Union        => "union",
             Enum         => "enum",
             Match        => "match",
             Mod          => "mod",
@@ -289,11 +315,30 @@ impl<T: Buffer> Lexer<T> {
             Break        => "break",
             Continue     => "continue",
             Static       => "static",
+            Unsafe       => "unsafe",
             Volatile     => "volatile",
+            Assert       => "assert",
+            Panic        => "panic",
+            Unreachable  => "unreachable",
+            AtomicLoad   => "atomic_load",
+            AtomicStore  => "atomic_store",
+            AtomicCas    => "atomic_cas",
+            AtomicFetchAdd => "atomic_fetch_add",
+            AtomicFence  => "atomic_fence",
+            Asm          => "asm",
+            Sizeof       => "sizeof",
+            Offsetof     => "offsetof",
+            New          => "new",
+            NewArray     => "new_array",
+            Free         => "free",
+            Extern       => "extern",
+            Pub          => "pub",
+            Import       => "import",
 
             // Basic types; TODO: add more.
             IntTypeTok   => IntTypeRule,
             Bool         => "bool",
+            Str          => "str",
 
             // Symbols
             LParen       => "(",
@@ -330,7 +375,11 @@ impl<T: Buffer> Lexer<T> {
             DoubleArrow  => "=>",
             Comma        => ",",
             QuestionMark => "?",
+            Hash         => "#",
+            HashBang     => "#!",
             Period       => ".",
+            DotDot       => "..",
+            DotDotDot    => "...",
             Underscore   => "_",
             PlusEq       => "+=",
             MinusEq      => "-=",
@@ -417,6 +466,7 @@ impl<T: Buffer> Iterator<SourceToken> for Lexer<T> {
                         }
 
                         // Advance our position within the line.
+                        let text = line.slice(self.pos.col, self.pos.col + longest);
                         self.pos.col += longest;
 
                         match best {
@@ -428,6 +478,7 @@ impl<T: Buffer> Iterator<SourceToken> for Lexer<T> {
                                 return Some(SourceToken {
                                     tok: tok,
                                     sp: sp,
+                                    text: text.to_string(),
                                 })
                             }
                         }
@@ -441,6 +492,7 @@ impl<T: Buffer> Iterator<SourceToken> for Lexer<T> {
                     return self.eof.take().map(|eof| SourceToken {
                         tok: eof,
                         sp: mk_sp(self.pos, 0),
+                        text: String::new(),
                     });
                 }
             }
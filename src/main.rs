@@ -21,7 +21,7 @@ use getopts::{getopts, reqopt, optopt, optflag};
 use getopts::OptionMissing;
 use std::ascii::StrAsciiExt;
 
-use std::io::stdio;
+use std::io::{stdio, File, BufferedReader};
 use std::os;
 
 mod util;
@@ -36,6 +36,8 @@ mod package;
 mod ir;
 mod target;
 mod values;
+mod consteval;
+mod interface;
 
 struct NullTarget;
 impl Target for NullTarget {
@@ -43,8 +45,28 @@ impl Target for NullTarget {
     fn compile(&self, _: Package) { }
 }
 
-fn package_from_stdin() -> Package {
-    Package::new("<stdin>", stdio::stdin())
+// The program in the file at `path`, or on stdin if there's no path.
+fn read_package(path: Option<&String>) -> Package {
+    match path {
+        Some(path) => {
+            let file = match File::open(&Path::new(path.as_slice())) {
+                Ok(file) => file,
+                Err(e) => fail!("Couldn't open {}: {}", path, e),
+            };
+            Package::new(path.as_slice(), BufferedReader::new(file))
+        }
+        None => Package::new("<stdin>", stdio::stdin()),
+    }
+}
+
+// The include guard of the header for the program at `path`, made from the
+// file's name, so that a C file can include the headers of several programs.
+fn header_guard(path: &str) -> String {
+    let stem = Path::new(path).filestem_str().unwrap_or("exports").to_ascii_upper();
+    let stem: String = stem.as_slice().chars()
+        .map(|c| if c.is_digit() || (c >= 'A' && c <= 'Z') { c } else { '_' })
+        .collect();
+    format!("MC_{}_H", stem)
 }
 
 fn new_target<T: Target>(args: Vec<String>) -> T {
@@ -65,6 +87,12 @@ fn main() {
 
     let opts = [
         optopt("", "target", "Set the output target.", "[c|null]"),
+        optflag("", "overflow-checks", "Trap on integer overflow, division by zero, and over-wide shifts."),
+        optflag("", "debug-checks", "Trap on out of bounds indexing, null dereferences, and bad enum accesses."),
+        optflag("", "release", "Skip the checks of assertions, but not their side effects, and trap silently in unreachable code."),
+        optflag("", "freestanding", "Don't depend on the C library, or treat main as the entry point."),
+        optopt("", "emit", "Set what to emit.", "[code|header|interface]"),
+        optopt("", "header-guard", "Set the include guard of an emitted header.", "NAME"),
         optflag("h", "help", "Show this help message."),
     ];
 
@@ -77,7 +105,7 @@ fn main() {
             None => {}
         }
 
-        let brief = format!("Usage: {} [OPTIONS]", arg0);
+        let brief = format!("Usage: {} [OPTIONS] [FILE]", arg0);
         println!("{}", getopts::usage(brief.as_slice(), opts));
     };
 
@@ -90,15 +118,56 @@ fn main() {
         return bail(None);
     }
 
+    if matches.free.len() > 1 {
+        return bail(Some("Only one input file can be given"));
+    }
+    let input = matches.free.as_slice().get(0);
+
     let targets = targets! {
         "c" => CTarget,
         "null" => NullTarget,
     };
 
     let target_arg = matches.opt_str("target").unwrap_or(String::from_str("null"));
+
+    // Options that the target interprets itself.
+    let mut target_opts = vec!();
+    if matches.opt_present("overflow-checks") {
+        target_opts.push(String::from_str("--overflow-checks"));
+    }
+    if matches.opt_present("debug-checks") {
+        target_opts.push(String::from_str("--debug-checks"));
+    }
+    if matches.opt_present("release") {
+        target_opts.push(String::from_str("--release"));
+    }
+    if matches.opt_present("freestanding") {
+        target_opts.push(String::from_str("--freestanding"));
+    }
+    let emit = matches.opt_str("emit").unwrap_or(String::from_str("code"));
+    match emit.as_slice() {
+        "code" | "header" => target_opts.push(format!("--emit={}", emit)),
+        // Interfaces are the same whatever the target.
+        "interface" => {
+            let package = read_package(input);
+            print!("{}", interface::module_interface(&package.session, &package.module));
+            return;
+        }
+        _ => {
+            let msg = format!("Unrecognized emit kind `{}'", emit);
+            return bail(Some(msg.as_slice()));
+        }
+    }
+    // Without a file, there's no name to make the guard from, and the target
+    // falls back to one of its own.
+    match matches.opt_str("header-guard").or(input.map(|path| header_guard(path.as_slice()))) {
+        Some(guard) => target_opts.push(format!("--header-guard={}", guard)),
+        None => {}
+    }
+
     let target = match targets.move_iter()
                         .filter(|&(ref t, _)| t.eq_ignore_ascii_case(target_arg.as_slice()))
-                        .map(|(_, ctor)| ctor(vec!()))
+                        .map(|(_, ctor)| ctor(target_opts.clone()))
                         .next() {
         Some(t) => t,
         None => {
@@ -107,14 +176,19 @@ fn main() {
         }
     };
 
-    let package = package_from_stdin();
+    let package = read_package(input);
     target.compile(package);
 }
 
 #[cfg(ir_tests)]
 fn main() {
     mod intermediate_tests;
-    intermediate_tests::main();
+    let mode = if os::args().iter().any(|a| a.as_slice() == "--overflow-checks") {
+        util::Checked
+    } else {
+        util::Wrapping
+    };
+    intermediate_tests::main(mode);
 }
 
 #[cfg(test)]
@@ -134,20 +208,13 @@ mod tests {
     #[test]
     fn exercise() {
         let src = r"
-mod prelude {
-    enum Option<T> {
-        Some(T),
-        None,
-    }
-}
-
 struct Foo<T> {
     foo: T,
 }
 
 fn main() {
     let wot = Foo { foo: 42 };
-    let more_wot = prelude::Some(wot);
+    let more_wot = Some(wot);
     let foo: u32 = match more_wot {
         ::prelude::Some(wot) => {
             match wot {
@@ -160,4 +227,105 @@ fn main() {
         let package = package_from_str(src);
         NullTarget.compile(package);
     }
+
+    // The message that `f` fails with.
+    fn failure(f: proc(): Send) -> String {
+        use std::task;
+        match task::try(f) {
+            Ok(()) => fail!("compiled without an error"),
+            Err(e) => match e.move::<String>() {
+                Ok(msg) => *msg,
+                Err(e) => match e.move::<&'static str>() {
+                    Ok(msg) => msg.to_string(),
+                    Err(_) => fail!("failed without a message"),
+                },
+            },
+        }
+    }
+
+    // The message that compiling `src` to C fails with.
+    fn c_failure(src: &'static str) -> String {
+        failure(proc() {
+            use target::CTarget;
+            let target: CTarget = Target::new(vec!());
+            target.compile(package_from_str(src));
+        })
+    }
+
+    #[test]
+    fn duplicate_export() {
+        let msg = c_failure(r"
+mod a {
+    pub extern fn area() -> u32 { 1 }
+}
+
+pub extern fn area() -> u32 { 2 }
+");
+        assert!(msg.as_slice().ends_with("`area' is already the name of another exported function"),
+                "{}", msg);
+    }
+
+    #[test]
+    fn reserved_export() {
+        let msg = c_failure(r"
+pub extern fn __mc_trap() {}
+");
+        assert!(msg.as_slice().starts_with("<input>:2:15: "), "{}", msg);
+        assert!(msg.as_slice().ends_with("`__mc_trap' can't be exported, as the C code \
+                                          uses that name itself"), "{}", msg);
+
+        let msg = c_failure(r"
+pub extern fn stderr() {}
+");
+        assert!(msg.as_slice().ends_with("`stderr' can't be exported, as the C code \
+                                          uses that name itself"), "{}", msg);
+    }
+
+    #[test]
+    fn no_prelude() {
+        let msg = failure(proc() {
+            package_from_str(r"
+#![no_prelude]
+
+fn main() {
+    let nothing: u32 = min(1, 2);
+}
+");
+        });
+        assert_eq!(msg.as_slice(), "Unresolved name min");
+    }
+
+    #[test]
+    fn own_prelude() {
+        let msg = failure(proc() {
+            package_from_str(r"
+mod prelude {
+    fn helper() {}
+}
+
+fn main() {
+    prelude::helper();
+}
+");
+        });
+        assert_eq!(msg.as_slice(),
+                   "\nA module can't be named prelude, unless the program has #![no_prelude]\n\
+                    at line 2, column 1");
+    }
+
+    #[test]
+    fn own_prelude_without_prelude() {
+        let package = package_from_str(r"
+#![no_prelude]
+
+mod prelude {
+    fn helper() {}
+}
+
+fn main() {
+    prelude::helper();
+}
+");
+        NullTarget.compile(package);
+    }
 }
@@ -17,12 +17,12 @@
  * nodes we build.
  */
 
-use std::collections::{HashMap, TreeMap};
+use std::collections::{HashMap, TreeMap, TreeSet};
 use session::Interner;
 use span::{SourcePos, Span, mk_sp};
-use util::Name;
+use util::{Name, IntKind, GenericInt, SignedInt, UnsignedInt, Width8, Width16, Width32};
 
-use std::{io, mem, num, vec};
+use std::{io, mem, num, os, vec};
 use std::iter::Peekable;
 
 use ast::*;
@@ -38,12 +38,26 @@ pub struct Parser {
     spanmap: TreeMap<NodeId, Span>,
     /// Tracks the corresponding file name of each AST node.
     filemap: TreeMap<NodeId, Name>,
+    /// The idents of the functions that are exported to C.
+    exports: TreeSet<NodeId>,
+    /// The items brought in by `import`, rather than written in the program.
+    imported: TreeSet<NodeId>,
+    /// The absolute paths of the files that have been imported, so that
+    /// each is only imported once.
+    imports: TreeSet<String>,
+    /// The attributes written before each item, struct field, enum variant,
+    /// function argument and statement, by the NodeId of the node (or of
+    /// the ident, for variants and arguments).
+    attrs: TreeMap<NodeId, Vec<Attr>>,
 }
 
 /// The state for parsing a stream of tokens into an AST node
 pub struct StreamParser<'a, T> {
     /// The token stream.
     tokens: Peekable<SourceToken, Lexer< T>>,
+    /// Tokens taken from the stream by looking more than one ahead, which
+    /// come before the rest of it.
+    ahead: Vec<SourceToken>,
     /// The name of the current stream being parsed.
     name: Name,
     /// The span corresponding to the last token we consumed from the stream.
@@ -54,6 +68,8 @@ pub struct StreamParser<'a, T> {
     interner: &'a mut Interner,
     /// Any parsing restriction in the current context
     restriction: Restriction,
+    /// If set, the text of each token consumed is added to this.
+    recording: Option<String>,
 }
 
 #[deriving(PartialEq, Eq)]
@@ -160,6 +176,10 @@ impl Parser {
             next_id: 0,
             spanmap: TreeMap::new(),
             filemap: TreeMap::new(),
+            exports: TreeSet::new(),
+            imported: TreeSet::new(),
+            imports: TreeSet::new(),
+            attrs: TreeMap::new(),
         }
     }
 
@@ -173,6 +193,38 @@ impl Parser {
         *self.filemap.find(id).unwrap()
     }
 
+    /// Whether the function with the given ident is exported to C.
+    pub fn is_exported(&self, id: &NodeId) -> bool {
+        self.exports.contains(id)
+    }
+
+    /// Whether the item with the given NodeId came from an imported file.
+    pub fn is_imported(&self, id: &NodeId) -> bool {
+        self.imported.contains(id)
+    }
+
+    /// The attribute with the given name on the node with the given NodeId,
+    /// if it has one.
+    pub fn find_attr<'a>(&'a self, id: &NodeId, name: &str) -> Option<&'a Attr> {
+        self.attrs.find(id).and_then(|attrs| attrs.iter().find(|attr| attr.val.name() == name))
+    }
+
+    /// All the attributes on the node with the given NodeId, in order.
+    pub fn attrs_of<'a>(&'a self, id: &NodeId) -> Option<&'a Vec<Attr>> {
+        self.attrs.find(id)
+    }
+
+    /// The integer type of the tag of the enum whose item has the given
+    /// NodeId: the one named by `#[repr(...)]`, or i32.
+    pub fn enum_tag_kind(&self, id: &NodeId) -> IntKind {
+        match self.find_attr(id, "repr") {
+            Some(&WithId { val: ListAttr(_, ref args), .. }) => {
+                from_str(args.get(0).as_slice()).unwrap_or(SignedInt(Width32))
+            }
+            _ => SignedInt(Width32),
+        }
+    }
+
     fn new_id(&mut self) -> NodeId {
         let id = self.next_id;
         self.next_id += 1;
@@ -183,6 +235,10 @@ impl Parser {
         self.parse_with(lexer, interner, |p| p.parse_module())
     }
 
+    pub fn parse_program<T: Buffer>(&mut self, lexer: Lexer<T>, interner: &mut Interner) -> Module {
+        self.parse_with(lexer, interner, |p| p.parse_program())
+    }
+
     pub fn parse_with<T: Buffer, U>(&mut self,
                                     lexer: Lexer<T>,
                                     interner: &mut Interner,
@@ -218,15 +274,20 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         StreamParser {
             name: name,
             tokens: tokens,
+            ahead: vec!(),
             parser: parser,
             interner: interner,
             last_span: mk_sp(SourcePos::new(), 0),
             restriction: NoRestriction,
+            recording: None,
         }
     }
 
     /// Peek at the Span of the next token.
     fn peek_span(&mut self) -> Span {
+        if !self.ahead.is_empty() {
+            return self.ahead.get(0).sp;
+        }
         match self.tokens.peek() {
             Some(st) => st.sp,
             None => fail!("At EOF."),
@@ -236,16 +297,47 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
     /// "Peek" at the next token, returning the token, without consuming
     /// it from the stream.
     fn peek<'a>(&'a mut self) -> &'a Token {
+        if !self.ahead.is_empty() {
+            return &self.ahead.get(0).tok;
+        }
         match self.tokens.peek() {
             Some(st) => &st.tok,
             None => fail!("Tried to peek past EOF"),
         }
     }
 
+    /// Peek at the token `n` past the next one, so that `peek_nth(0)` is
+    /// the same as `peek()`.
+    fn peek_nth<'a>(&'a mut self, n: uint) -> &'a Token {
+        while self.ahead.len() <= n {
+            match self.tokens.next() {
+                Some(st) => self.ahead.push(st),
+                None => fail!("Tried to peek past EOF"),
+            }
+        }
+        &self.ahead.get(n).tok
+    }
+
     /// Consume the next token from the stream, returning it.
     fn eat(&mut self) -> Token {
-        match self.tokens.next() {
+        let next = if self.ahead.is_empty() {
+            self.tokens.next()
+        } else {
+            self.ahead.shift()
+        };
+        match next {
             Some(st) => {
+                match self.recording {
+                    Some(ref mut text) => {
+                        // Tokens that weren't next to each other in the
+                        // source get a space between them.
+                        if !text.is_empty() && st.sp.get_begin() != self.last_span.get_end() {
+                            text.push_char(' ');
+                        }
+                        text.push_str(st.text.as_slice());
+                    }
+                    None => {}
+                }
                 self.last_span = st.sp;
                 st.tok
             }
@@ -269,6 +361,11 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         fail!("\n{}\nat {}", message.as_slice(), pos)
     }
 
+    fn warn<'a, T: Str>(&self, message: T, pos: SourcePos) {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "warning: {}\nat {}", message.as_slice(), pos);
+    }
+
     /// A convenience function to generate an error message when we've
     /// peeked at a token, but it doesn't match any token we were expecting.
     fn peek_error<'a, T: Str>(&mut self, message: T) -> ! {
@@ -278,10 +375,16 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
     }
 
     fn add_id_and_span<T>(&mut self, val: T, sp: Span) -> WithId<T> {
+        let id = self.new_id_with_span(sp);
+        WithId { val: val, id: id }
+    }
+
+    /// An id for a node that isn't wrapped in a WithId, like a struct field.
+    fn new_id_with_span(&mut self, sp: Span) -> NodeId {
         let id = self.parser.new_id();
         self.parser.spanmap.insert(id, sp);
         self.parser.filemap.insert(id, self.name);
-        WithId { val: val, id: id }
+        id
     }
 
     /// Utility to parse a comma-separated list of things
@@ -372,9 +475,16 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
             _ => false,
         };
 
+        let first = self.parse_ident();
+        self.parse_path_rest(start_span, global, first, with_tps)
+    }
+
+    /// Parse the remainder of a path whose first element has already been
+    /// consumed.
+    fn parse_path_rest(&mut self, start_span: Span, global: bool, first: Ident, with_tps: bool) -> Path {
         let mut path = PathNode {
             global: global,
-            elems: vec!(self.parse_ident()),
+            elems: vec!(first),
         };
 
         while *self.peek() == ColonColon {
@@ -415,12 +525,45 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         }
     }
 
+    /// Process the escape sequences in the body of a string literal.
+    fn unescape_str(&self, s: &str) -> String {
+        let mut res = String::new();
+        let mut chars = s.chars();
+        loop {
+            let c = match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some('n')  => '\n',
+                    Some('t')  => '\t',
+                    Some('r')  => '\r',
+                    Some('0')  => '\0',
+                    Some('\\') => '\\',
+                    Some('"')  => '"',
+                    Some('\'') => '\'',
+                    Some('x')  => {
+                        let digits: String = chars.by_ref().take(2).collect();
+                        match num::from_str_radix::<u8>(digits.as_slice(), 16) {
+                            Some(b) if b < 0x80 => b as char,
+                            _ => self.error(format!("Invalid escape \\\\x{} in string literal", digits),
+                                            self.last_span.get_begin()),
+                        }
+                    }
+                    c => self.error(format!("Invalid escape in string literal: {}", c),
+                                    self.last_span.get_begin()),
+                },
+                Some(c) => c,
+                None => break,
+            };
+            res.push_char(c);
+        }
+        res
+    }
+
     pub fn parse_lit(&mut self) -> Lit {
         let node = match self.eat() {
             True                 => BoolLit(true),
             False                => BoolLit(false),
             Null                 => NullLit,
-            StringTok(s)         => StringLit(s),
+            StringTok(s)         => StringLit(self.unescape_str(s.as_slice())),
             NumberTok(num, kind) => NumLit(num, kind),
             tok                  => self.error(format!("Unexpected {} where literal expected", tok), self.last_span.get_begin())
         };
@@ -428,49 +571,55 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         self.add_id_and_span(node, self.last_span)
     }
 
-    fn parse_pat_common(&mut self, allow_types: bool) -> Pat {
-        let start_span = self.peek_span();
+    fn parse_pat_type(&mut self, allow_types: bool) -> Option<Type> {
+        match *self.peek() {
+            Colon if allow_types => {
+                self.expect(Colon);
+                let t = self.parse_type();
+                Some(t)
+            }
+            _ => None
+        }
+    }
 
-        let maybe_type = |p: &mut StreamParser<'a, T>, allow_types| {
-            match *p.peek() {
-                Colon if allow_types => {
-                    p.expect(Colon);
-                    let t = p.parse_type();
-                    Some(t)
+    /// Parse the rest of a pattern that starts with a path: a variant
+    /// pattern, a struct pattern, or a plain identifier.
+    fn parse_path_pat(&mut self, path: Path, allow_types: bool) -> PatNode {
+        match *self.peek() {
+            LParen => {
+                self.expect(LParen);
+                let args = self.parse_list(|p| p.parse_pat_common(allow_types), RParen, false);
+                self.expect(RParen);
+                VariantPat(path, args)
+            }
+            DoubleArrow => {
+                // Empty variant.
+                VariantPat(path, vec!())
+            }
+            LBrace => {
+                self.expect(LBrace);
+                let field_pats = self.parse_list(|p| p.parse_field_pat(), RBrace, true);
+                self.expect(RBrace);
+                StructPat(path, field_pats)
+            }
+            _ => {
+                if path.val.global || path.val.elems.len() != 1 {
+                    self.error(String::from_str("Expected ident, found path"), self.last_span.get_begin());
                 }
-                _ => None
+                let mut elems = path.val.elems;
+                let ident = elems.pop().unwrap();
+                IdentPat(ident, self.parse_pat_type(allow_types))
             }
-        };
+        }
+    }
+
+    fn parse_pat_common(&mut self, allow_types: bool) -> Pat {
+        let start_span = self.peek_span();
 
         let pat = match *self.peek() {
             ColonColon | IdentTok(..) => {
                 let path = self.parse_path();
-                match *self.peek() {
-                    LParen => {
-                        self.expect(LParen);
-                        let args = self.parse_list(|p| p.parse_pat_common(allow_types), RParen, false);
-                        self.expect(RParen);
-                        VariantPat(path, args)
-                    }
-                    DoubleArrow => {
-                        // Empty variant.
-                        VariantPat(path, vec!())
-                    }
-                    LBrace => {
-                        self.expect(LBrace);
-                        let field_pats = self.parse_list(|p| p.parse_field_pat(), RBrace, true);
-                        self.expect(RBrace);
-                        StructPat(path, field_pats)
-                    }
-                    _ => {
-                        if path.val.global || path.val.elems.len() != 1 {
-                            self.error(String::from_str("Expected ident, found path"), self.last_span.get_begin());
-                        }
-                        let mut elems = path.val.elems;
-                        let ident = elems.pop().unwrap();
-                        IdentPat(ident, maybe_type(self, allow_types))
-                    }
-                }
+                self.parse_path_pat(path, allow_types)
             }
             LParen => {
                 self.expect(LParen);
@@ -480,7 +629,7 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
             }
             Underscore => {
                 self.expect(Underscore);
-                DiscardPat(maybe_type(self, allow_types))
+                DiscardPat(self.parse_pat_type(allow_types))
             }
             _ => self.peek_error("Unexpected token while parsing pattern")
         };
@@ -520,9 +669,17 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
                 self.expect(Bool);
                 BoolType
             }
+            Str => {
+                self.expect(Str);
+                StrType
+            }
             Star => {
                 self.expect(Star);
-                PtrType(box self.parse_type())
+                let volatile = match *self.peek() {
+                    Volatile => { self.expect(Volatile); true }
+                    _ => false,
+                };
+                PtrType(box self.parse_type(), volatile)
             }
             LParen => {
                 self.expect(LParen);
@@ -740,6 +897,7 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
             | WhileExpr(..)
             | MatchExpr(..)
             | BlockExpr(..)
+            | UnsafeExpr(..)
               => self.restriction == ExprStmtRestriction,
             _ => false,
         }
@@ -868,14 +1026,25 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         let node = match *self.peek() {
             LBrace if self.restriction != NoAmbiguousLBraceRestriction => {
                 self.expect(LBrace);
-                let fields = self.parse_list(|p| {
-                    let name = p.parse_name();
-                    p.expect(Colon);
-                    let expr = p.parse_expr();
-                    (name, expr)
-                }, RBrace, true);
+                let mut fields = vec!();
+                let mut base = None;
+                while *self.peek() != RBrace {
+                    // Functional update, as in `Foo { x: 1, ..base }`. The
+                    // base must come last.
+                    if *self.peek() == DotDot {
+                        self.expect(DotDot);
+                        base = Some(box self.parse_expr());
+                        break;
+                    }
+
+                    fields.push(self.parse_struct_expr_field());
+                    match *self.peek() {
+                        Comma => { self.expect(Comma); }
+                        _ => break,
+                    }
+                }
                 self.expect(RBrace);
-                StructExpr(path, fields)
+                StructExpr(path, fields, base)
             }
             _ => PathExpr(path),
         };
@@ -883,6 +1052,27 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         self.add_id_and_span(node, start_span.to(self.last_span))
     }
 
+    fn parse_struct_expr_field(&mut self) -> (Name, Expr) {
+        let ident = self.parse_ident();
+        let name = ident.val.name;
+        match *self.peek() {
+            Colon => {
+                self.expect(Colon);
+                (name, self.parse_expr())
+            }
+            // Field-init shorthand: `Foo { x }` is `Foo { x: x }`.
+            _ => {
+                let sp = self.last_span;
+                let path = PathNode {
+                    global: false,
+                    elems: vec!(ident),
+                };
+                let path = self.add_id_and_span(path, sp);
+                (name, self.add_id_and_span(PathExpr(path), sp))
+            }
+        }
+    }
+
     fn parse_break_expr(&mut self) -> Expr {
         self.expect(Break);
         self.add_id_and_span(BreakExpr, self.last_span)
@@ -893,6 +1083,241 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         self.add_id_and_span(ContinueExpr, self.last_span)
     }
 
+    // `assert(cond)` or `assert(cond, msg)`.
+    fn parse_assert_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        self.expect(Assert);
+        self.expect(LParen);
+        let outer = mem::replace(&mut self.recording, Some(String::new()));
+        let cond = self.parse_expr();
+        let text = mem::replace(&mut self.recording, outer).unwrap();
+        let msg = match *self.peek() {
+            Comma => {
+                self.expect(Comma);
+                Some(box self.parse_expr())
+            }
+            _ => None,
+        };
+        self.expect(RParen);
+        self.add_id_and_span(AssertExpr(box cond, msg, text), start_span.to(self.last_span))
+    }
+
+    fn parse_panic_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        self.expect(Panic);
+        self.expect(LParen);
+        let msg = self.parse_expr();
+        self.expect(RParen);
+        self.add_id_and_span(PanicExpr(box msg), start_span.to(self.last_span))
+    }
+
+    fn parse_unreachable_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        self.expect(Unreachable);
+        self.expect(LParen);
+        self.expect(RParen);
+        self.add_id_and_span(UnreachableExpr, start_span.to(self.last_span))
+    }
+
+    // `atomic_load(p, order)`, and the like: the operands, then the memory
+    // ordering.
+    fn parse_atomic_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        let (op, nargs) = match self.eat() {
+            AtomicLoad     => (LoadOp, 1u),
+            AtomicStore    => (StoreOp, 2),
+            AtomicCas      => (CasOp, 3),
+            AtomicFetchAdd => (FetchAddOp, 2),
+            AtomicFence    => (FenceOp, 0),
+            _ => unreachable!(),
+        };
+        self.expect(LParen);
+        let mut args = vec!();
+        for _ in range(0, nargs) {
+            args.push(self.parse_expr());
+            self.expect(Comma);
+        }
+
+        let order_span = self.peek_span();
+        let order = match self.eat() {
+            IdentTok(ref s) if s.as_slice() == "relaxed" => Relaxed,
+            IdentTok(ref s) if s.as_slice() == "acquire" => Acquire,
+            IdentTok(ref s) if s.as_slice() == "release" => Release,
+            IdentTok(ref s) if s.as_slice() == "seq_cst" => SeqCst,
+            tok => self.error(format!("Expected a memory ordering (relaxed, acquire, release or seq_cst), found {}", tok),
+                              order_span.get_begin()),
+        };
+        // As in C, a load can't release and a store can't acquire.
+        match (op, order) {
+            (LoadOp, Release) | (StoreOp, Acquire) =>
+                self.error(format!("{} can't have {} ordering", op, order), order_span.get_begin()),
+            _ => {}
+        }
+        self.expect(RParen);
+        self.add_id_and_span(AtomicExpr(op, args, order), start_span.to(self.last_span))
+    }
+
+    // `asm!("template", in(reg) x, out("a") y, clobber("memory"))`. The
+    // operands can be `in`, `out` or `inout`, and their constraints are
+    // either `reg` or a GCC constraint. `{n}` in the template stands for the
+    // nth operand.
+    fn parse_asm_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        self.expect(Asm);
+        self.expect(Bang);
+        self.expect(LParen);
+        let template_span = self.peek_span();
+        let template = match self.eat() {
+            StringTok(s) => self.unescape_str(s.as_slice()),
+            tok => self.error(format!("Expected an asm template string, got {}", tok),
+                              template_span.get_begin()),
+        };
+
+        let mut operands = vec!();
+        let mut clobbers = vec!();
+        while *self.peek() == Comma {
+            self.expect(Comma);
+            if *self.peek() == RParen {
+                break;
+            }
+
+            let kind = match self.eat() {
+                IdentTok(ref s) if s.as_slice() == "in" => Some(AsmIn),
+                IdentTok(ref s) if s.as_slice() == "out" => Some(AsmOut),
+                IdentTok(ref s) if s.as_slice() == "inout" => Some(AsmInOut),
+                IdentTok(ref s) if s.as_slice() == "clobber" => None,
+                tok => self.error(format!("Expected in, out, inout or clobber, got {}", tok),
+                                  self.last_span.get_begin()),
+            };
+            self.expect(LParen);
+            match kind {
+                Some(kind) => {
+                    let constraint = match self.eat() {
+                        IdentTok(ref s) if s.as_slice() == "reg" => String::from_str("r"),
+                        StringTok(s) => self.unescape_str(s.as_slice()),
+                        tok => self.error(format!("Expected reg or a constraint string, got {}", tok),
+                                          self.last_span.get_begin()),
+                    };
+                    self.expect(RParen);
+                    let expr = self.parse_expr();
+                    operands.push(AsmOperand { kind: kind, constraint: constraint, expr: expr });
+                }
+                None => {
+                    match self.eat() {
+                        StringTok(s) => clobbers.push(self.unescape_str(s.as_slice())),
+                        tok => self.error(format!("Expected a clobber string, got {}", tok),
+                                          self.last_span.get_begin()),
+                    }
+                    self.expect(RParen);
+                }
+            }
+        }
+        self.expect(RParen);
+
+        // Check the placeholders now, while we know where the template is.
+        let mut chars = template.as_slice().chars().peekable();
+        loop {
+            match chars.next() {
+                Some('{') if chars.peek() == Some(&'{') => { chars.next(); }
+                Some('{') => {
+                    let mut n = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => n.push_char(c),
+                            None => self.error("Unclosed { in asm template",
+                                               template_span.get_begin()),
+                        }
+                    }
+                    match from_str::<uint>(n.as_slice()) {
+                        Some(i) if i < operands.len() => {}
+                        _ => self.error(format!("asm template refers to \\{{}\\}, but there are {} operands",
+                                                n, operands.len()),
+                                        template_span.get_begin()),
+                    }
+                }
+                Some('}') if chars.peek() == Some(&'}') => { chars.next(); }
+                Some('}') => self.error("Unmatched } in asm template", template_span.get_begin()),
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        self.add_id_and_span(AsmExpr(template, operands, clobbers), start_span.to(self.last_span))
+    }
+
+    // `sizeof(T)` and `offsetof(T, field)`, which take a struct's layout
+    // attributes into account.
+    fn parse_sizeof_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        let offset = match self.eat() {
+            Sizeof => false,
+            Offsetof => true,
+            _ => unreachable!(),
+        };
+        self.expect(LParen);
+        let t = self.parse_type();
+        let node = if offset {
+            self.expect(Comma);
+            let field = self.parse_name();
+            OffsetofExpr(t, field)
+        } else {
+            SizeofExpr(t)
+        };
+        self.expect(RParen);
+        self.add_id_and_span(node, start_span.to(self.last_span))
+    }
+
+    // `new(value)`, `new(T)`, `new_array(T, n)` and `free(p)`. Whether
+    // `new` is given a type or a value can't always be told from the
+    // syntax: a bare path might be either, so it's parsed as a value and
+    // left to the resolver. Anything that starts like a type is one, so a
+    // value that starts with `*` or `(` has to be put in a variable first.
+    fn parse_alloc_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        let tok = self.eat();
+        self.expect(LParen);
+        let node = match tok {
+            New => if self.type_is_next() {
+                NewTypeExpr(self.parse_type())
+            } else {
+                NewExpr(box self.with_restriction(NoRestriction, |p| p.parse_expr()))
+            },
+            NewArray => {
+                let t = self.parse_type();
+                self.expect(Comma);
+                NewArrayExpr(t, box self.with_restriction(NoRestriction, |p| p.parse_expr()))
+            }
+            Free => FreeExpr(box self.with_restriction(NoRestriction, |p| p.parse_expr())),
+            _ => unreachable!(),
+        };
+        self.expect(RParen);
+        self.add_id_and_span(node, start_span.to(self.last_span))
+    }
+
+    // Whether what's next can only be a type. A path is only known to be
+    // one if it has type parameters.
+    fn type_is_next(&mut self) -> bool {
+        match *self.peek() {
+            IntTypeTok(..) | Bool | Str | Fn | Star | LParen => return true,
+            ColonColon | IdentTok(..) => {}
+            _ => return false,
+        }
+
+        let mut n = if *self.peek() == ColonColon { 1 } else { 0 };
+        loop {
+            match *self.peek_nth(n) {
+                IdentTok(..) => n += 1,
+                _ => return false,
+            }
+            match *self.peek_nth(n) {
+                ColonColon => n += 1,
+                Less => return true,
+                _ => return false,
+            }
+        }
+    }
+
     fn parse_simple_expr(&mut self) -> Expr {
         let start_span = self.peek_span();
         let peek_next_expr_parser = |p: &mut StreamParser<'a, T>| match *p.peek() {
@@ -900,11 +1325,21 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
                 Return => Some(|p: &mut StreamParser<'a, T>| p.parse_return_expr()),
                 Break  => Some(|p: &mut StreamParser<'a, T>| p.parse_break_expr()),
                 Continue => Some(|p: &mut StreamParser<'a, T>| p.parse_continue_expr()),
+                Assert => Some(|p: &mut StreamParser<'a, T>| p.parse_assert_expr()),
+                Panic  => Some(|p: &mut StreamParser<'a, T>| p.parse_panic_expr()),
+                Unreachable => Some(|p: &mut StreamParser<'a, T>| p.parse_unreachable_expr()),
+                AtomicLoad | AtomicStore | AtomicCas | AtomicFetchAdd | AtomicFence =>
+                    Some(|p: &mut StreamParser<'a, T>| p.parse_atomic_expr()),
+                Asm    => Some(|p: &mut StreamParser<'a, T>| p.parse_asm_expr()),
+                Sizeof | Offsetof => Some(|p: &mut StreamParser<'a, T>| p.parse_sizeof_expr()),
+                New | NewArray | Free => Some(|p: &mut StreamParser<'a, T>| p.parse_alloc_expr()),
                 Match  => Some(|p: &mut StreamParser<'a, T>| p.parse_match_expr()),
                 For    => Some(|p: &mut StreamParser<'a, T>| p.parse_for_expr()),
                 While  => Some(|p: &mut StreamParser<'a, T>| p.parse_while_expr()),
                 LBrace => Some(|p: &mut StreamParser<'a, T>| p.parse_block_expr()),
+                Unsafe => Some(|p: &mut StreamParser<'a, T>| p.parse_unsafe_expr()),
                 LParen => Some(|p: &mut StreamParser<'a, T>| p.parse_paren_expr()),
+                LBracket => Some(|p: &mut StreamParser<'a, T>| p.parse_array_expr()),
                 ColonColon | IdentTok(..) => Some(|p: &mut StreamParser<'a, T>| p.parse_path_or_struct_expr()),
                 NumberTok(..) | StringTok(..) | True | False | Null => Some(|p: &mut StreamParser<'a, T>| {
                     let start_span = p.peek_span();
@@ -924,8 +1359,16 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
             let node = match *self.peek() {
                 Period => {
                     self.expect(Period);
-                    let field = self.parse_name();
-                    DotExpr(box expr, field)
+                    match *self.peek() {
+                        NumberTok(..) => {
+                            let idx = self.expect_number();
+                            TupleIndexExpr(box expr, idx)
+                        }
+                        _ => {
+                            let field = self.parse_name();
+                            DotExpr(box expr, field)
+                        }
+                    }
                 }
                 Arrow => {
                     self.expect(Arrow);
@@ -971,12 +1414,29 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         self.add_id_and_span(node, start_span.to(self.last_span))
     }
 
+    fn parse_array_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+
+        self.expect(LBracket);
+        let elems = self.with_restriction(NoRestriction, |p| p.parse_list(|p| p.parse_expr(), RBracket, true));
+        self.expect(RBracket);
+
+        self.add_id_and_span(ArrayExpr(elems), start_span.to(self.last_span))
+    }
+
     fn parse_block_expr(&mut self) -> Expr {
         let start_span = self.peek_span();
         let block = self.parse_block();
         self.add_id_and_span(BlockExpr(box block), start_span.to(self.last_span))
     }
 
+    fn parse_unsafe_expr(&mut self) -> Expr {
+        let start_span = self.peek_span();
+        self.expect(Unsafe);
+        let block = self.parse_block();
+        self.add_id_and_span(UnsafeExpr(box block), start_span.to(self.last_span))
+    }
+
     fn parse_stmt(&mut self) -> Stmt {
         match *self.peek() {
             Let => self.parse_let_stmt(),
@@ -1004,18 +1464,34 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         let mut statements = vec!();
         let mut items = vec!();
         loop {
+            let attrs = self.parse_attrs();
             match *self.peek() {
-                RBrace => break,
-                Fn | Struct | Enum | Mod => items.push(self.parse_item()),
-                _ => statements.push(self.parse_stmt()),
+                RBrace if attrs.is_empty() => break,
+                Fn | Struct | Union | Enum | Mod => {
+                    let item = self.parse_item_inner();
+                    self.add_item_attrs(&item, attrs);
+                    items.push(item);
+                }
+                _ => {
+                    let stmt = self.parse_stmt();
+                    self.add_attrs(stmt.id, attrs, None);
+                    statements.push(stmt);
+                }
             }
         }
 
         self.expect(RBrace);
 
         let expr = statements.pop().and_then(|stmt| {
+            let id = stmt.id;
             match stmt.val {
                 ExprStmt(expr) => {
+                    // The statement goes away, so its attributes go to
+                    // the expression.
+                    match self.parser.attrs.pop(&id) {
+                        Some(attrs) => { self.parser.attrs.insert(expr.id, attrs); }
+                        None => {}
+                    }
                     Some(expr)
                 }
                 _ => {
@@ -1032,20 +1508,72 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         }
     }
 
-    fn parse_func_arg(&mut self) -> FuncArg {
+    fn parse_func_arg(&mut self) -> (FuncArg, Option<Pat>) {
         /* Parse a single argument as part of a function declaration.
            For example, in
            `let f(x: int, y: int) -> int { ... }`,
            this would parse "`x: int`" or "`y: int`".
+
+           An argument may also be an irrefutable pattern, as in
+           `(x, y): (int, int)`. Such an argument is given a fresh name,
+           and the pattern is handed back so that the caller can
+           destructure the argument at the top of the function body.
         */
-        let arg_id = self.parse_ident();
+        let attrs = self.parse_attrs();
+        let start_span = self.peek_span();
+        let (arg_id, pat) = match *self.peek() {
+            IdentTok(..) => {
+                let ident = self.parse_ident();
+                match *self.peek() {
+                    Colon => (ident, None),
+                    _ => {
+                        let path = self.parse_path_rest(start_span, false, ident, true);
+                        let node = self.parse_path_pat(path, false);
+                        let pat = self.add_id_and_span(node, start_span.to(self.last_span));
+                        (self.fresh_arg_ident(&pat), Some(pat))
+                    }
+                }
+            }
+            _ => {
+                let pat = self.parse_typeless_pat();
+                (self.fresh_arg_ident(&pat), Some(pat))
+            }
+        };
         self.expect(Colon);
         let arg_type = self.parse_type();
 
-        FuncArg {
+        self.add_attrs(arg_id.id, attrs, None);
+        let arg = FuncArg {
             ident: arg_id,
             argtype: arg_type,
-        }
+        };
+
+        (arg, pat)
+    }
+
+    /// Make up a name for a function argument that was written as a pattern.
+    fn fresh_arg_ident(&mut self, pat: &Pat) -> Ident {
+        let ident = IdentNode {
+            name: self.interner.intern(format!("__arg{}", pat.id.to_uint())),
+            tps: None,
+        };
+
+        let sp = self.parser.span_of(&pat.id);
+        self.add_id_and_span(ident, sp)
+    }
+
+    /// Build `let <pat> = <ident>;`, which destructures a function argument
+    /// that was written as a pattern.
+    fn destructure_arg(&mut self, ident: &Ident, pat: Pat) -> Stmt {
+        let sp = self.parser.span_of(&pat.id);
+        let elem = self.add_id_and_span(ident.val.clone(), sp);
+        let path = PathNode {
+            global: false,
+            elems: vec!(elem),
+        };
+        let path = self.add_id_and_span(path, sp);
+        let expr = self.add_id_and_span(PathExpr(path), sp);
+        self.add_id_and_span(LetStmt(pat, Some(expr)), sp)
     }
 
     fn parse_func_item(&mut self) -> Item {
@@ -1054,9 +1582,34 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         let funcname = self.parse_ident();
         let type_params = self.parse_item_type_params(LParen);
         self.expect(LParen);
-        let args = self.parse_list(|p| p.parse_func_arg(), RParen, false);
+        let parsed_args = self.parse_list(|p| p.parse_func_arg(), RParen, false);
         self.expect(RParen);
-        let return_type = match *self.peek() {
+        let return_type = self.parse_return_type();
+        let mut body = self.parse_block();
+
+        // Arguments written as patterns are destructured by `let`s that
+        // we slip in ahead of the rest of the body.
+        let mut args = vec!();
+        let mut arg_lets = vec!();
+        for (arg, pat) in parsed_args.move_iter() {
+            match pat {
+                Some(pat) => {
+                    let stmt = self.destructure_arg(&arg.ident, pat);
+                    arg_lets.push(stmt);
+                }
+                None => {}
+            }
+            args.push(arg);
+        }
+        arg_lets.push_all_move(body.stmts);
+        body.stmts = arg_lets;
+
+        self.add_id_and_span(FuncItem(funcname, args, return_type, body, type_params),
+                         start_span.to(self.last_span))
+    }
+
+    fn parse_return_type(&mut self) -> Type {
+        match *self.peek() {
             Arrow => {
                 self.expect(Arrow);
                 self.parse_type()
@@ -1065,36 +1618,131 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
                 let dummy_span = mk_sp(self.last_span.get_end(), 0);
                 self.add_id_and_span(UnitType, dummy_span)
             }
+        }
+    }
+
+    fn parse_extern_func_item(&mut self) -> Item {
+        /* An extern function has no body; it is provided by whatever the
+           program is linked against. For example,
+           `extern fn printf(fmt: *u8, ...) -> i32;`
+           or, to call it by another name,
+           `extern fn put(c: i32) -> i32 as "putchar";`
+        */
+        let start_span = self.peek_span();
+        self.expect(Extern);
+        self.expect(Fn);
+        let funcname = self.parse_ident();
+        let type_params = self.parse_item_type_params(LParen);
+        self.expect(LParen);
+        let mut args = vec!();
+        let mut variadic = false;
+        while *self.peek() != RParen {
+            if *self.peek() == DotDotDot {
+                // C can't declare a variadic function without a fixed
+                // argument to find the rest from.
+                if args.is_empty() {
+                    self.error("A variadic extern function needs at least one fixed argument",
+                               self.peek_span().get_begin());
+                }
+                self.expect(DotDotDot);
+                variadic = true;
+                break;
+            }
+            let attrs = self.parse_attrs();
+            let ident = self.parse_ident();
+            self.add_attrs(ident.id, attrs, None);
+            self.expect(Colon);
+            let arg_type = self.parse_type();
+            args.push(FuncArg {
+                ident: ident,
+                argtype: arg_type,
+            });
+            if *self.peek() != RParen {
+                self.expect(Comma);
+            }
+        }
+        self.expect(RParen);
+        let return_type = self.parse_return_type();
+        let link_name = match *self.peek() {
+            As => {
+                self.expect(As);
+                match self.eat() {
+                    StringTok(s) => Some(self.unescape_str(s.as_slice())),
+                    tok => self.error(format!("Expected a link name string, got {}", tok),
+                                      self.last_span.get_begin()),
+                }
+            }
+            _ => None,
         };
-        let body = self.parse_block();
-        self.add_id_and_span(FuncItem(funcname, args, return_type, body, type_params),
-                         start_span.to(self.last_span))
+        self.expect(Semicolon);
+
+        self.add_id_and_span(ExternFuncItem(funcname, args, return_type, type_params,
+                                            variadic, link_name),
+                             start_span.to(self.last_span))
+    }
+
+    fn parse_export_func_item(&mut self) -> Item {
+        /* A `pub extern fn` is an ordinary function that C code can call
+           too, by its own unmangled name. For example,
+           `pub extern fn checksum(buf: *u8, len: u32) -> u32 { ... }`
+        */
+        let start_pos = self.peek_span().get_begin();
+        self.expect(Pub);
+        self.expect(Extern);
+        let item = self.parse_func_item();
+        match item.val {
+            FuncItem(ref id, _, _, _, ref tps) => {
+                if !tps.is_empty() {
+                    self.error("Exported functions cannot have type parameters", start_pos);
+                }
+                self.parser.exports.insert(id.id);
+            }
+            _ => unreachable!(),
+        }
+        item
     }
 
     fn parse_struct_field(&mut self) -> Field {
+        let attrs = self.parse_attrs();
+        let start_span = self.peek_span();
         let name = self.parse_name();
         self.expect(Colon);
         let field_type = self.parse_type();
+        let id = self.new_id_with_span(start_span.to(self.last_span));
+        self.add_attrs(id, attrs, None);
 
         Field {
+            id:      id,
             name:    name,
             fldtype: field_type,
         }
     }
 
     fn parse_struct_item(&mut self) -> Item {
+        /* Unions are written just like structs: `union bits { f: u32, b: u8[4] }`. */
         let start_span = self.peek_span();
-        self.expect(Struct);
+        let is_union = match self.eat() {
+            Struct => false,
+            Union => true,
+            _ => unreachable!(),
+        };
         let structname = self.parse_ident();
         let type_params = self.parse_item_type_params(LBrace);
         self.expect(LBrace);
         let body = self.parse_list(|p| p.parse_struct_field(), RBrace, true);
         self.expect(RBrace);
-        self.add_id_and_span(StructItem(structname, body, type_params), start_span.to(self.last_span))
+        let node = if is_union {
+            UnionItem(structname, body, type_params)
+        } else {
+            StructItem(structname, body, type_params)
+        };
+        self.add_id_and_span(node, start_span.to(self.last_span))
     }
 
     fn parse_variant(&mut self) -> Variant {
+        let attrs = self.parse_attrs();
         let ident = self.parse_ident();
+        self.add_attrs(ident.id, attrs, None);
         let types = match *self.peek() {
             LParen => {
                 self.expect(LParen);
@@ -1107,9 +1755,34 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
             }
         };
 
+        let disc = match *self.peek() {
+            Eq => {
+                self.expect(Eq);
+                if !types.is_empty() {
+                    self.error("Only a variant without fields can be given a discriminant",
+                               self.last_span.get_begin());
+                }
+                let negative = *self.peek() == Dash;
+                if negative {
+                    self.expect(Dash);
+                }
+                match self.eat() {
+                    NumberTok(n, GenericInt) if n <= ::std::i64::MAX as u64 =>
+                        Some(if negative { -(n as i64) } else { n as i64 }),
+                    NumberTok(n, GenericInt) =>
+                        self.error(format!("Discriminant {} is too big", n),
+                                   self.last_span.get_begin()),
+                    tok => self.error(format!("Expected an unsuffixed number for the discriminant, found {}", tok),
+                                      self.last_span.get_begin()),
+                }
+            }
+            _ => None,
+        };
+
         Variant {
             ident: ident,
             args:  types,
+            disc:  disc,
         }
     }
 
@@ -1121,6 +1794,15 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         self.expect(LBrace);
         let body = self.parse_list(|p| p.parse_variant(), RBrace, true);
         self.expect(RBrace);
+
+        let discs = discriminants(&body);
+        for (i, variant) in body.iter().enumerate() {
+            if discs.slice_to(i).contains(discs.get(i)) {
+                self.error(format!("Discriminant {} is used more than once", discs.get(i)),
+                           self.parser.span_of(&variant.ident.id).get_begin());
+            }
+        }
+
         self.add_id_and_span(EnumItem(enumname, body, type_params), start_span.to(self.last_span))
     }
 
@@ -1128,13 +1810,58 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
         let start_span = self.peek_span();
         let mut items = vec!();
         while *self.peek() != end {
-            items.push(self.parse_item());
+            match *self.peek() {
+                // The imported items are as good as the file's own, so they
+                // can't be hidden in a module.
+                Import if end == Eof => {
+                    let imported = self.parse_import();
+                    items.push_all_move(imported);
+                }
+                Import => self.error("Imports are only allowed at the top level of a file",
+                                     self.peek_span().get_begin()),
+                _ => items.push(self.parse_item()),
+            }
         }
         let node = ModuleNode { items: items };
         let end_span = self.peek_span();
         self.add_id_and_span(node, start_span.to(end_span))
     }
 
+    fn parse_import(&mut self) -> Vec<Item> {
+        /* `import "geometry.mh";` brings in the items of an interface file,
+           as written by `--emit interface`, as though they appeared here.
+           The path is relative to the directory of the file being parsed.
+           A file that's already been imported, by whatever path, brings
+           nothing in the second time.
+        */
+        self.expect(Import);
+        let path = match self.eat() {
+            StringTok(s) => self.unescape_str(s.as_slice()),
+            tok => self.error(format!("Expected the path of an interface file, got {}", tok),
+                              self.last_span.get_begin()),
+        };
+        self.expect(Semicolon);
+
+        let dir = Path::new(self.interner.name_to_str(&self.name)).dir_path();
+        let path = os::make_absolute(&dir.join(path));
+        if !self.parser.imports.insert(format!("{}", path.display())) {
+            return vec!();
+        }
+        let file = match io::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => self.error(format!("Couldn't import {}: {}", path.display(), e),
+                                 self.last_span.get_begin()),
+        };
+
+        let lexer = Lexer::new(format!("{}", path.display()), io::BufferedReader::new(file));
+        let mut imported = StreamParser::new(lexer, &mut *self.interner, &mut *self.parser);
+        let items = imported.parse_module().val.items;
+        for item in items.iter() {
+            self.parser.imported.insert(item.id);
+        }
+        items
+    }
+
     fn parse_mod_item(&mut self) -> Item {
         let start_span = self.peek_span();
         self.expect(Mod);
@@ -1168,22 +1895,214 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
                              start_span.to(self.last_span))
     }
 
+    // `#[name]`, `#[name(arg, ...)]`, where the arguments are names or
+    // numbers, or `#[name = "value"]`. An attribute that starts with `#!`
+    // instead is about the file it's at the top of.
+    fn parse_attr(&mut self) -> Attr {
+        let start_span = self.peek_span();
+        match self.eat() {
+            Hash | HashBang => {}
+            tok => self.error(format!("Expected an attribute, found {}", tok),
+                              self.last_span.get_begin()),
+        }
+        self.expect(LBracket);
+        let name = match self.eat() {
+            IdentTok(s) => s,
+            tok => self.error(format!("Expected an attribute name, found {}", tok),
+                              self.last_span.get_begin()),
+        };
+        let node = match *self.peek() {
+            LParen => {
+                self.expect(LParen);
+                let args = self.parse_list(|p| match p.eat() {
+                    IdentTok(s) => s,
+                    NumberTok(n, _) => format!("{}", n),
+                    IntTypeTok(k) => format!("{}", k),
+                    tok => p.error(format!("Expected a name or number in attribute, found {}", tok),
+                                   p.last_span.get_begin()),
+                }, RParen, false);
+                self.expect(RParen);
+                ListAttr(name, args)
+            }
+            Eq => {
+                self.expect(Eq);
+                match self.eat() {
+                    StringTok(s) => ValueAttr(name, self.unescape_str(s.as_slice())),
+                    tok => self.error(format!("Expected a string for the value of {}, found {}",
+                                              name, tok),
+                                      self.last_span.get_begin()),
+                }
+            }
+            _ => WordAttr(name),
+        };
+        self.expect(RBracket);
+        self.add_id_and_span(node, start_span.to(self.last_span))
+    }
+
+    fn parse_attrs(&mut self) -> Vec<Attr> {
+        let mut attrs = vec!();
+        while *self.peek() == Hash {
+            attrs.push(self.parse_attr());
+        }
+        attrs
+    }
+
+    // The attributes the compiler knows are all about the layout of
+    // structs, unions and enums: `#[repr(C)]`, which is how structs are laid
+    // out anyway, `#[repr(u8)]` and the like for the tag of an enum,
+    // `#[packed]`, and `#[align(N)]`. Anything else is kept, in case a later
+    // pass wants it, but gets a warning.
+    fn check_attrs(&self, attrs: &Vec<Attr>, item: Option<&ItemNode>) {
+        let (on_aggregate, on_enum) = match item {
+            Some(&StructItem(..)) | Some(&UnionItem(..)) => (true, false),
+            Some(&EnumItem(..)) => (false, true),
+            _ => (false, false),
+        };
+
+        for attr in attrs.iter() {
+            let pos = self.parser.span_of(&attr.id).get_begin();
+            let layout = match attr.val {
+                ListAttr(ref name, ref args) if name.as_slice() == "repr" => {
+                    if !on_aggregate && !on_enum {
+                        self.error(format!("{} can only be used on a struct, union or enum", attr), pos);
+                    }
+                    let supported = args.len() == 1 && (args.get(0).as_slice() == "C" ||
+                        (on_enum && from_str::<IntKind>(args.get(0).as_slice()).is_some()));
+                    if !supported {
+                        self.error(format!("Unsupported representation in {}", attr), pos);
+                    }
+                    false
+                }
+                WordAttr(ref name) if name.as_slice() == "packed" => true,
+                ListAttr(ref name, ref args) if name.as_slice() == "align" => {
+                    let align = if args.len() == 1 {
+                        from_str::<uint>(args.get(0).as_slice())
+                    } else {
+                        None
+                    };
+                    match align {
+                        Some(n) if n != 0 && n & (n - 1) == 0 => {}
+                        _ => self.error(format!("{} needs a power of two", attr), pos),
+                    }
+                    true
+                }
+                _ => {
+                    self.warn(format!("Unknown attribute {}", attr), pos);
+                    false
+                }
+            };
+
+            if layout && !on_aggregate {
+                self.error(format!("{} can only be used on a struct or union", attr), pos);
+            }
+
+            if attrs.iter().filter(|a| a.val.name() == attr.val.name()).count() > 1 {
+                self.error(format!("{} is given more than once", attr), pos);
+            }
+        }
+    }
+
+    fn add_attrs(&mut self, id: NodeId, attrs: Vec<Attr>, item: Option<&ItemNode>) {
+        if !attrs.is_empty() {
+            self.check_attrs(&attrs, item);
+            self.parser.attrs.insert(id, attrs);
+        }
+    }
+
+    fn add_item_attrs(&mut self, item: &Item, attrs: Vec<Attr>) {
+        self.add_attrs(item.id, attrs, Some(&item.val));
+
+        // Every tag has to fit the enum's tag type, which the attributes
+        // may have picked.
+        match item.val {
+            EnumItem(_, ref variants, _) => {
+                let (bits, signed) = match self.parser.enum_tag_kind(&item.id) {
+                    SignedInt(w) => (w, true),
+                    UnsignedInt(w) => (w, false),
+                    GenericInt => unreachable!(),
+                };
+                let bits = match bits {
+                    Width8 => 8u,
+                    Width16 => 16,
+                    _ => 32,
+                };
+                let (min, max) = if signed {
+                    (-(1i64 << (bits - 1)), (1i64 << (bits - 1)) - 1)
+                } else {
+                    (0, (1i64 << bits) - 1)
+                };
+                for (variant, disc) in variants.iter().zip(discriminants(variants).iter()) {
+                    if *disc < min || *disc > max {
+                        self.error(format!("Discriminant {} doesn't fit the enum's tag type", disc),
+                                   self.parser.span_of(&variant.ident.id).get_begin());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn parse_item(&mut self) -> Item {
+        let attrs = self.parse_attrs();
+        let item = self.parse_item_inner();
+        self.add_item_attrs(&item, attrs);
+        item
+    }
+
+    fn parse_item_inner(&mut self) -> Item {
         match *self.peek() {
             Fn => self.parse_func_item(),
-            Struct => self.parse_struct_item(),
+            Extern => self.parse_extern_func_item(),
+            Pub => self.parse_export_func_item(),
+            Struct | Union => self.parse_struct_item(),
             Enum => self.parse_enum_item(),
             Mod => self.parse_mod_item(),
             Static => self.parse_static_item(),
-            _ => self.peek_error("Expected an item definition (fn, struct, enum, mod)"),
+            _ => self.peek_error("Expected an item definition (fn, extern, pub extern, struct, union, enum, mod, static)"),
         }
     }
 
     pub fn parse_module(&mut self) -> Module {
         /* This is the highest level node of the AST. This function
          * is the one that will parse an entire file. */
+        let mut attrs = vec!();
+        while *self.peek() == HashBang {
+            attrs.push(self.parse_attr());
+        }
         let module = self.parse_module_until(Eof);
         self.expect(Eof);
+
+        // The only attribute a file can have is `#![no_prelude]`, which
+        // leaves the prelude out of it.
+        for attr in attrs.iter() {
+            let pos = self.parser.span_of(&attr.id).get_begin();
+            match attr.val {
+                WordAttr(ref name) if name.as_slice() == "no_prelude" => {}
+                _ => self.warn(format!("Unknown attribute {}", attr), pos),
+            }
+        }
+        if !attrs.is_empty() {
+            self.parser.attrs.insert(module.id, attrs);
+        }
+        module
+    }
+
+    pub fn parse_program(&mut self) -> Module {
+        /* A whole program, as opposed to the prelude or an interface. Unless
+           it goes without the prelude, it can't have a module of that name
+           of its own, which would shadow the prelude and lose its items. */
+        let module = self.parse_module();
+        if self.parser.find_attr(&module.id, "no_prelude").is_none() {
+            for item in module.val.items.iter() {
+                match item.val {
+                    ModItem(ref id, _) if self.interner.name_to_str(&id.val.name) == "prelude" => {
+                        self.error("A module can't be named prelude, unless the program has #![no_prelude]",
+                                   self.parser.span_of(&item.id).get_begin());
+                    }
+                    _ => {}
+                }
+            }
+        }
         module
     }
 }
@@ -1192,7 +2111,12 @@ impl<'a, T: Buffer> StreamParser<'a, T> {
 mod tests {
     use super::*;
     use super::Parser;
-    use ast::Expr;
+    use ast::{Expr, StringLit, ExternFuncItem, FuncItem, ModItem, StructItem, UnionItem, EnumItem};
+    use ast::{NewExpr, NewTypeExpr};
+    use ast::discriminants;
+    use session::Interner;
+    use lexer::lexer_from_str;
+    use util::{UnsignedInt, Width8};
 
     #[test]
     fn test_basic_arith_expr() {
@@ -1232,6 +2156,311 @@ mod tests {
                    "((1+((3*5)/2))-((2*3)*((5+6))))");
     }
 
+    #[test]
+    fn test_string_escapes() {
+        let (_, lit) = ast_from_str(r#""a\tb\"c\\d\x41\n""#, |p| p.parse_lit());
+        assert_eq!(lit.val, StringLit(String::from_str("a\tb\"c\\dA\n")));
+    }
+
+    #[test]
+    fn test_assert_exprs() {
+        let (_, tree) = ast_from_str(r#"assert(1 < 3, "big") + unreachable()"#, |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(),
+                   "(assert((1<3), \"big\")+unreachable())");
+    }
+
+    #[test]
+    fn test_atomic_exprs() {
+        let (_, tree) = ast_from_str("atomic_fetch_add(null, 1, relaxed) + atomic_cas(null, 0, 2, seq_cst)",
+                                     |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(),
+                   "(atomic_fetch_add(null, 1, relaxed)+atomic_cas(null, 0, 2, seq_cst))");
+        let (_, fence) = ast_from_str("atomic_fence(acquire)", |p| p.parse_expr());
+        assert_eq!(format!("{}", fence).as_slice(), "atomic_fence(acquire)");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_atomic_load_release() {
+        ast_from_str("atomic_load(null, release)", |p| p.parse_expr());
+    }
+
+    #[test]
+    fn test_asm_expr() {
+        let (_, tree) = ast_from_str(r#"asm!("rdtsc\nmov {1}, %eax", out("a") *null, in(reg) 5, clobber("memory"),)"#,
+                                     |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(),
+                   r#"asm!("rdtsc\nmov {1}, %eax", out("a") (*null), in("r") 5, clobber("memory"))"#);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_asm_bad_placeholder() {
+        ast_from_str(r#"asm!("nop {1}", in(reg) 1)"#, |p| p.parse_expr());
+    }
+
+    #[test]
+    fn test_array_expr() {
+        let (_, tree) = ast_from_str("[1, 2+3, [4],]", |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(), "[1, (2+3), [4]]");
+    }
+
+    #[test]
+    fn test_volatile_ptr_type() {
+        let (_, t) = ast_from_str("*volatile *u8", |p| p.parse_type());
+        assert_eq!(format!("{}", t).as_slice(), "*volatile (*(u8))");
+    }
+
+    #[test]
+    fn test_extern_func_item() {
+        let (_, tree) = ast_from_str(r#"extern fn put(c: i32, ...) -> i32 as "putchar";"#,
+                                     |p| p.parse_item());
+        match tree.val {
+            ExternFuncItem(_, ref args, _, ref tps, variadic, ref link_name) => {
+                assert_eq!(args.len(), 1);
+                assert!(tps.is_empty());
+                assert!(variadic);
+                assert_eq!(link_name, &Some(String::from_str("putchar")));
+            }
+            _ => fail!("Expected an extern fn, got {}", tree),
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_extern_func_item_only_variadic() {
+        ast_from_str("extern fn f(...) -> i32;", |p| p.parse_item());
+    }
+
+    #[test]
+    fn test_export_func_item() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("pub extern fn f(x: u32) -> u32 { x }");
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        match item.val {
+            FuncItem(ref id, _, _, _, _) => assert!(parser.is_exported(&id.id)),
+            _ => fail!("Expected a fn, got {}", item),
+        }
+    }
+
+    #[test]
+    fn test_struct_attrs() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("#[repr(C)] #[packed]\n#[align(8)] struct s { a: u8, b: u32 }");
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        let attrs: Vec<String> = parser.attrs_of(&item.id).unwrap().iter()
+            .map(|attr| format!("{}", attr)).collect();
+        assert_eq!(attrs.connect(" ").as_slice(), "#[repr(C)] #[packed] #[align(8)]");
+        assert!(parser.find_attr(&item.id, "packed").is_some());
+        assert!(parser.find_attr(&item.id, "inline").is_none());
+    }
+
+    #[test]
+    fn test_attrs_everywhere() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str(r#"
+#[doc = "A \"thing\""]
+fn f(#[unused] x: u32) -> u32 {
+    #[lint(allow, dead_code)] let y = x;
+    #[cold] y
+}"#);
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        assert_eq!(format!("{}", parser.find_attr(&item.id, "doc").unwrap()).as_slice(),
+                   r#"#[doc = "A \"thing\""]"#);
+        match item.val {
+            FuncItem(_, ref args, _, ref block, _) => {
+                assert!(parser.find_attr(&args.get(0).ident.id, "unused").is_some());
+                let stmt = block.stmts.get(0);
+                assert_eq!(format!("{}", parser.find_attr(&stmt.id, "lint").unwrap()).as_slice(),
+                           "#[lint(allow, dead_code)]");
+                let expr = block.expr.as_ref().unwrap();
+                assert!(parser.find_attr(&expr.id, "cold").is_some());
+            }
+            _ => fail!("Expected a fn, got {}", item),
+        }
+    }
+
+    #[test]
+    fn test_field_and_variant_attrs() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("mod m { struct s { #[deprecated] a: u8 } enum e { #[default] A, B } }");
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        let items = match item.val {
+            ModItem(_, ref module) => module.val.items.clone(),
+            _ => fail!("Expected a mod, got {}", item),
+        };
+        match items.get(0).val {
+            StructItem(_, ref fields, _) =>
+                assert!(parser.find_attr(&fields.get(0).id, "deprecated").is_some()),
+            _ => fail!("Expected a struct"),
+        }
+        match items.get(1).val {
+            EnumItem(_, ref variants, _) => {
+                assert!(parser.find_attr(&variants.get(0).ident.id, "default").is_some());
+                assert!(parser.attrs_of(&variants.get(1).ident.id).is_none());
+            }
+            _ => fail!("Expected an enum"),
+        }
+    }
+
+    #[test]
+    fn test_union_item() {
+        let (_, item) = ast_from_str("#[packed] union u { a: u8, b: u32, }", |p| p.parse_item());
+        match item.val {
+            UnionItem(_, ref fields, ref tps) => {
+                assert_eq!(fields.len(), 2);
+                assert!(tps.is_empty());
+            }
+            _ => fail!("Expected a union, got {}", item),
+        }
+    }
+
+    #[test]
+    fn test_unsafe_expr() {
+        let (_, tree) = ast_from_str("unsafe { 1 } + 2", |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(), "(unsafe {\n    1\n}+2)");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_packed_field() {
+        ast_from_str("struct s { #[packed] a: u8 }", |p| p.parse_item());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_bad_align() {
+        ast_from_str("#[align(3)] struct s { a: u8 }", |p| p.parse_item());
+    }
+
+    #[test]
+    fn test_file_attrs() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("#![no_prelude]\n#[packed] struct s { a: u8 }");
+        let module = parser.parse_with(lexer, &mut interner, |p| p.parse_module());
+        assert!(parser.find_attr(&module.id, "no_prelude").is_some());
+        let item = module.val.items.get(0);
+        assert!(parser.find_attr(&item.id, "packed").is_some());
+        assert!(parser.find_attr(&item.id, "no_prelude").is_none());
+    }
+
+    #[test]
+    fn test_enum_discriminants() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("#[repr(u8)] enum e { a = 0x10, b, c = 2, d(u32) }");
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        match item.val {
+            EnumItem(_, ref variants, _) => {
+                assert_eq!(discriminants(variants), vec!(16, 17, 2, 3));
+            }
+            _ => fail!("Expected an enum, got {}", item),
+        }
+        assert_eq!(parser.enum_tag_kind(&item.id), UnsignedInt(Width8));
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_duplicate_discriminant() {
+        ast_from_str("enum e { a = 1, b = 0, c }", |p| p.parse_item());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_discriminant_too_big() {
+        ast_from_str("#[repr(i8)] enum e { a = 0x80 }", |p| p.parse_item());
+    }
+
+    #[test]
+    fn test_negative_discriminants() {
+        let mut parser = Parser::new();
+        let mut interner = Interner::new();
+        let lexer = lexer_from_str("#[repr(i8)] enum e { a = -128, b, c = 127 }");
+        let item = parser.parse_with(lexer, &mut interner, |p| p.parse_item());
+        match item.val {
+            EnumItem(_, ref variants, _) => {
+                assert_eq!(discriminants(variants), vec!(-128, -127, 127));
+            }
+            _ => fail!("Expected an enum, got {}", item),
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_discriminant_too_small() {
+        ast_from_str("#[repr(i8)] enum e { a = -129 }", |p| p.parse_item());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_negative_discriminant_unsigned() {
+        ast_from_str("#[repr(u16)] enum e { a = -1 }", |p| p.parse_item());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_repr_int_on_struct() {
+        ast_from_str("#[repr(u8)] struct s { a: u8 }", |p| p.parse_item());
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_packed_fn() {
+        ast_from_str("#[packed] fn f() {}", |p| p.parse_item());
+    }
+
+    #[test]
+    fn test_sizeof_expr() {
+        let (_, tree) = ast_from_str("sizeof(*u8) * 2", |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(), "(sizeof(*(u8))*2)");
+    }
+
+    #[test]
+    fn test_alloc_exprs() {
+        let (_, tree) = ast_from_str("new(u32)", |p| p.parse_expr());
+        match tree.val {
+            NewTypeExpr(..) => {}
+            _ => fail!("Expected new of a type"),
+        }
+        let (_, tree) = ast_from_str("new(ll)", |p| p.parse_expr());
+        match tree.val {
+            NewExpr(..) => {}
+            _ => fail!("Expected new of a value"),
+        }
+        let (_, tree) = ast_from_str("free(new_array(*u8, n + 1))", |p| p.parse_expr());
+        assert_eq!(format!("{}", tree).as_slice(), "free(new_array(*(u8), (n+1)))");
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_import_in_mod() {
+        ast_from_str(r#"mod a { import "a.mh"; }"#, |p| p.parse_module());
+    }
+
+    #[test]
+    fn test_new_of_types() {
+        for src in ["new(*ll)", "new(**u8)", "new((u32, *ll))", "new(Option<u32>)",
+                    "new(::prelude::Option<*ll>)", "new(list<T>[4])"].iter() {
+            let (_, tree) = ast_from_str(*src, |p| p.parse_expr());
+            match tree.val {
+                NewTypeExpr(..) => {}
+                _ => fail!("Expected new of a type in {}", src),
+            }
+        }
+        for src in ["new(ll + 3)", "new(a::b)", "new(a::b + 1)", "new(f(x))"].iter() {
+            let (_, tree) = ast_from_str(*src, |p| p.parse_expr());
+            match tree.val {
+                NewExpr(..) => {}
+                _ => fail!("Expected new of a value in {}", src),
+            }
+        }
+    }
+
     // These tests disabled until we have a pretty printer
     /*
     fn compare_canonicalized(raw: &str, parsed: &str) {
@@ -1,6 +1,6 @@
 use ast::*;
 use ast::visit::*;
-use std::collections::{SmallIntMap, TreeMap};
+use std::collections::{SmallIntMap, TreeMap, TreeSet};
 
 use session::Interner;
 use util::Name;
@@ -44,10 +44,11 @@ impl Subscope {
     fn insert_items(&mut self, items: &Vec<Item>) {
         for item in items.iter() {
             match item.val {
-                FuncItem(ref ident, _, _, _, _) => {
+                FuncItem(ref ident, _, _, _, _) |
+                ExternFuncItem(ref ident, _, _, _, _, _) => {
                     self.insert(ValNS, ident);
                 }
-                StructItem(ref ident, _, _) => {
+                StructItem(ref ident, _, _) | UnionItem(ref ident, _, _) => {
                     self.insert(TypeAndModNS, ident);
                     self.insert(StructNS, ident);
                 }
@@ -77,6 +78,11 @@ impl Subscope {
 
 pub struct Resolver {
     table: TreeMap<NodeId, NodeId>,
+    // The module whose items are in scope everywhere, by the NodeId of its
+    // ident.
+    prelude: Option<NodeId>,
+    // The paths given to `new` that name types rather than values.
+    new_types: TreeSet<NodeId>,
 }
 
 struct ModuleCollector {
@@ -95,14 +101,33 @@ impl Resolver {
     pub fn new() -> Resolver {
         Resolver {
             table: TreeMap::new(),
+            prelude: None,
+            new_types: TreeSet::new(),
         }
     }
 
+    /// Make the items of the given module visible everywhere, behind
+    /// anything else of the same name.
+    pub fn set_prelude(&mut self, module: NodeId) {
+        self.prelude = Some(module);
+    }
+
+    /// Whether the given module ident is the prelude's.
+    pub fn is_prelude(&self, module: &NodeId) -> bool {
+        self.prelude == Some(*module)
+    }
+
     /// Get the NodeId of the item that defines the given path
     pub fn def_from_path(&self, path: &Path) -> NodeId {
         *self.table.find(&path.id).unwrap()
     }
 
+    /// Whether the path given to a `new` names the type to allocate,
+    /// rather than a value to copy.
+    pub fn is_new_type(&self, path: &Path) -> bool {
+        self.new_types.contains(&path.id)
+    }
+
     /// The entry point for the resolver
     pub fn resolve_module(&mut self, interner: &Interner, module: &Module) {
         ModuleResolver::process(self, interner, module);
@@ -136,6 +161,15 @@ impl<'a> ModuleResolver<'a> {
 
     /// Search the current scope stack local-to-global for a matching ident in the requested namespace
     fn resolve_path(&mut self, ns: NS, path: &Path) {
+        match self.try_resolve_path(ns, path) {
+            Ok(()) => {}
+            Err(name) => fail!("Unresolved name {}", self.interner.name_to_str(&name)),
+        }
+    }
+
+    /// Like resolve_path, but hands back the first name that isn't found
+    /// rather than failing.
+    fn try_resolve_path(&mut self, ns: NS, path: &Path) -> Result<(), Name> {
         use std::slice;
 
         fn make_scope<'a>(scope: &'a [Subscope], modscope: &'a ModuleScope) -> &'a [Subscope] {
@@ -145,15 +179,17 @@ impl<'a> ModuleResolver<'a> {
             }
         }
 
-        fn resolve_ident(resolver: &mut Resolver, interner: &Interner, scope: &[Subscope], ns: NS, ident: &Ident) -> NodeId {
+        fn resolve_ident(resolver: &mut Resolver, scope: &[Subscope], prelude: Option<&Subscope>,
+                         ns: NS, ident: &Ident) -> Result<NodeId, Name> {
             match scope.iter().rev()
+                              .chain(prelude.move_iter())
                               .filter_map(|subscope| subscope.find(ns, ident))
                               .next() {
                 Some(node_id) => {
                     resolver.table.insert(ident.id, node_id);
-                    node_id
+                    Ok(node_id)
                 }
-                None => fail!("Unresolved name {}", interner.name_to_str(&ident.val.name)),
+                None => Err(ident.val.name.clone()),
             }
         }
 
@@ -169,14 +205,27 @@ impl<'a> ModuleResolver<'a> {
             self.scope.as_slice()
         };
 
+        // The first name of a path can come from the prelude too, except
+        // inside the prelude, where its items are in scope already.
+        let mut prelude = match self.resolver.prelude {
+            Some(id) if !path.val.global => match self.tree.find(&id) {
+                Some(&OffBranch(ref subscope)) => Some(subscope),
+                _ => None,
+            },
+            _ => None,
+        };
+
         for elem in path.val.elems.init().iter() {
-            let node_id = resolve_ident(self.resolver, self.interner, search_scope, TypeAndModNS, elem);
-            search_scope = make_scope(search_scope, self.tree.find(&node_id).unwrap());
+            let node_id = try!(resolve_ident(self.resolver, search_scope, prelude,
+                                             TypeAndModNS, elem));
+            search_scope = make_scope(self.scope.as_slice(), self.tree.find(&node_id).unwrap());
+            prelude = None;
         }
 
         let terminal = path.val.elems.last().unwrap();
-        let node_id = resolve_ident(self.resolver, self.interner, search_scope, ns, terminal);
+        let node_id = try!(resolve_ident(self.resolver, search_scope, prelude, ns, terminal));
         self.resolver.table.insert(path.id, node_id);
+        Ok(())
     }
 
     /// Adds the given ident to the given namespace in the current scope
@@ -204,6 +253,9 @@ impl Visitor for ModuleCollector {
                 let mut subscope = Subscope::new();
                 subscope.insert_items(&module.val.items);
                 self.tree.insert(ident.id, OffBranch(subscope));
+
+                // Collect any modules nested inside this one too.
+                self.visit_module(module);
             }
             _ => {}
         }
@@ -251,11 +303,25 @@ impl<'a> Visitor for ModuleResolver<'a> {
             PathExpr(ref path) => {
                 self.resolve_path(ValNS, path);
             }
-            StructExpr(ref path, ref flds) => {
+            // `new(x)` allocates a copy of the value x, unless there's no
+            // such value and x is a type instead.
+            NewExpr(ref e) => match e.val {
+                PathExpr(ref path) => {
+                    if self.try_resolve_path(ValNS, path).is_err() {
+                        self.resolve_path(TypeAndModNS, path);
+                        self.resolver.new_types.insert(path.id);
+                    }
+                }
+                _ => self.visit_expr(*e),
+            },
+            StructExpr(ref path, ref flds, ref base) => {
                 self.resolve_path(StructNS, path);
                 for fld in flds.iter() {
                     self.visit_expr(fld.ref1());
                 }
+                for base in base.iter() {
+                    self.visit_expr(*base);
+                }
             }
             MatchExpr(ref e, ref arms) => {
                 self.visit_expr(*e);
@@ -302,6 +368,17 @@ impl<'a> Visitor for ModuleResolver<'a> {
                     me.visit_block(block);
                 });
             }
+            ExternFuncItem(_, ref args, ref t, ref tps, _, _) => {
+                self.descend(None, |me| {
+                    for tp in tps.iter() {
+                        me.add_to_scope(TypeAndModNS, tp);
+                    }
+                    me.visit_type(t);
+                    for arg in args.iter() {
+                        me.visit_type(&arg.argtype);
+                    }
+                });
+            }
             StaticItem(ref ident, ref ty, ref expr) => {
                 self.visit_ident(ident);
                 for t in ty.iter() {
@@ -311,7 +388,7 @@ impl<'a> Visitor for ModuleResolver<'a> {
                     self.visit_expr(e);
                 }
             }
-            StructItem(_, ref fields, ref tps) => {
+            StructItem(_, ref fields, ref tps) | UnionItem(_, ref fields, ref tps) => {
                 self.descend(None, |me| {
                     for tp in tps.iter() {
                         me.add_to_scope(TypeAndModNS, tp);
@@ -337,6 +414,13 @@ impl<'a> Visitor for ModuleResolver<'a> {
             ModItem(ref ident, ref module) => {
                 use std::mem;
 
+                // If we're already inside a module, find it, since its scope
+                // is on the branch we're about to swap out.
+                let parent = self.tree.iter().find(|&(_, s)| match *s {
+                    OnBranch(..) => true,
+                    _ => false,
+                }).map(|(id, _)| *id);
+
                 // Find the subscope we're about to descend into and swap it out of the tree
                 let mut scope = match self.tree.swap(ident.id, OnBranch(0)) {
                     Some(OffBranch(scope)) => vec!(scope),
@@ -358,8 +442,23 @@ impl<'a> Visitor for ModuleResolver<'a> {
                     _ => None,
                 };
 
+                // Put the parent module's scope back in the tree, so that
+                // paths through it still resolve from inside the child
+                for &id in parent.iter() {
+                    let mut subscope = Subscope::new();
+                    mem::swap(&mut subscope, scope.get_mut(0));
+                    self.tree.swap(id, OffBranch(subscope));
+                }
+
                 self.visit_module(module);
 
+                for &id in parent.iter() {
+                    match self.tree.swap(id, OnBranch(0)) {
+                        Some(OffBranch(subscope)) => { *scope.get_mut(0) = subscope; }
+                        _ => unreachable!(),
+                    }
+                }
+
                 // Pop all the context we saved above
                 match old_root_idx {
                     Some(idx) => {
@@ -390,7 +489,7 @@ impl<'a> Visitor for ModuleResolver<'a> {
 #[cfg(test)]
 mod tests {
     use super::Resolver;
-    use ast::NodeId;
+    use ast::{NodeId, ModItem};
     use ast::visit::Visitor;
     use parser::ast_from_str;
     use std::collections::TreeMap;
@@ -402,6 +501,77 @@ mod tests {
         resolver.resolve_module(&mut interner, &tree);
     }
 
+    #[test]
+    fn nested_modules() {
+        let (mut interner, tree) = ast_from_str(r"
+mod a {
+    mod b {
+        fn f() {}
+    }
+    fn g() { b::f(); ::a::b::f(); }
+}
+fn main() { a::b::f(); a::g(); }", |p| p.parse_module());
+        let mut resolver = Resolver::new();
+        resolver.resolve_module(&mut interner, &tree);
+    }
+
+    fn resolve_with_prelude(src: &str) {
+        let (mut interner, tree) = ast_from_str(src, |p| p.parse_module());
+        let mut resolver = Resolver::new();
+        match tree.val.items.get(0).val {
+            ModItem(ref id, _) => resolver.set_prelude(id.id),
+            _ => fail!("Expected the prelude first"),
+        }
+        resolver.resolve_module(&mut interner, &tree);
+    }
+
+    #[test]
+    fn prelude_everywhere() {
+        resolve_with_prelude(r"
+mod prelude {
+    enum Opt { Yes(u32), No }
+    fn helper() -> Opt { No }
+}
+mod a {
+    mod b {
+        fn f() -> Opt { helper() }
+    }
+    // A name of its own comes first.
+    fn helper() {}
+    fn g() { helper(); b::f(); }
+}
+fn main() { let x: Opt = Yes(1); prelude::helper(); }");
+    }
+
+    #[test]
+    #[should_fail]
+    fn prelude_not_through_paths() {
+        resolve_with_prelude(r"
+mod prelude {
+    fn helper() {}
+}
+mod a {}
+fn main() { a::helper(); }");
+    }
+
+    #[test]
+    fn new_type_or_value() {
+        let (mut interner, tree) = ast_from_str(r"
+struct ll { data: i32, next: *ll }
+fn f<T>(t: T) { let a = new(T); let b = new(t); let c = new(ll); let ll = 5; let d = new(ll); }",
+                                                |p| p.parse_module());
+        let mut resolver = Resolver::new();
+        resolver.resolve_module(&mut interner, &tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn new_unresolved() {
+        let (mut interner, tree) = ast_from_str("fn f() { let p = new(wot); }", |p| p.parse_module());
+        let mut resolver = Resolver::new();
+        resolver.resolve_module(&mut interner, &tree);
+    }
+
     #[test]
     #[should_fail]
     fn unresolved_name() {
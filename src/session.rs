@@ -8,7 +8,7 @@ use std::io;
 
 use std::collections::{HashMap, TreeMap};
 use span::Span;
-use ast::Module;
+use ast::{Item, Module, ModItem};
 use ast::defmap::DefMap;
 use resolver::Resolver;
 use parser::Parser;
@@ -16,6 +16,9 @@ use lexer::Lexer;
 use ast::visit::Visitor;
 use util::Name;
 
+// Parsed into every program that doesn't opt out with `#![no_prelude]`.
+static PRELUDE: &'static str = include_str!("prelude.mc");
+
 pub struct Session {
     pub defmap: DefMap,
     pub resolver: Resolver,
@@ -69,12 +72,29 @@ impl Session {
 
     pub fn parse_buffer<S: StrAllocating, T: Buffer>(&mut self, name: S, buffer: T) -> Module {
         let lexer = Lexer::new(name, buffer);
-        let module = self.parser.parse(lexer, &mut self.interner);
+        let mut module = self.parser.parse_program(lexer, &mut self.interner);
+        if self.parser.find_attr(&module.id, "no_prelude").is_none() {
+            let prelude = self.parse_prelude();
+            module.val.items.insert(0, prelude);
+        }
         self.defmap.visit_module(&module);
         self.resolver.resolve_module(&self.interner, &module);
         module
     }
 
+    // The prelude's `mod prelude` item, which the resolver is told about.
+    fn parse_prelude(&mut self) -> Item {
+        let bytes = Vec::from_slice(PRELUDE.as_bytes());
+        let buffer = io::BufferedReader::new(io::MemReader::new(bytes));
+        let mut module = self.parser.parse(Lexer::new("<prelude>", buffer), &mut self.interner);
+        let item = module.val.items.pop().unwrap();
+        match item.val {
+            ModItem(ref id, _) => self.resolver.set_prelude(id.id),
+            _ => unreachable!(),
+        }
+        item
+    }
+
     pub fn parse_file(&mut self, file: io::File) -> Module {
         let filename = format!("{}", file.path().display());
         self.parse_buffer(filename, io::BufferedReader::new(file))
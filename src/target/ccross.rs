@@ -0,0 +1,2233 @@
+use ast::*;
+use ast::defmap::*;
+use values::*;
+use consteval::*;
+use typechecker::*;
+use std::io::stdio;
+use lexer::Lexer;
+use parser::Parser;
+use ast::visit::{Visitor, walk_item, walk_module};
+use std::collections::treemap::{TreeSet, TreeMap};
+use resolver::Resolver;
+use session::Session;
+use package::Package;
+use target::Target;
+use util::Name;
+use util::{GenericInt, SignedInt, UnsignedInt};
+use util::{Width, AnyWidth, Width8, Width16, Width32};
+use util::{OverflowMode, Wrapping, Checked};
+use std::cell::{Cell, RefCell};
+use std::mem::replace;
+
+struct CCrossCompiler {
+    // The C name of every item, keyed by the NodeId of its ident.
+    names: TreeMap<NodeId, String>,
+    // For each enum variant, the enum it belongs to and its discriminant.
+    variants: TreeMap<NodeId, (NodeId, i64)>,
+    // Every item in the program, hoisted out of modules and blocks.
+    items: Vec<Item>,
+    // Every C name that's been given out so far.
+    used: RefCell<TreeSet<String>>,
+    // The C names of the instances of generic items, keyed by the item and
+    // the mangled type arguments.
+    instances: RefCell<TreeMap<(NodeId, String), String>>,
+    // Instances of generic functions that are used, but not yet emitted.
+    fn_queue: RefCell<Vec<(NodeId, Vec<Ty>, String)>>,
+    // Whether the function being emitted is a unit entry point; see
+    // is_unit_entry.
+    in_entry: Cell<bool>,
+    // The extern functions that are used, which are the only ones declared.
+    used_externs: RefCell<TreeSet<NodeId>>,
+    // Every struct, enum, and tuple type that's used, by C name.
+    aggregates: RefCell<TreeMap<String, Ty>>,
+    // The type arguments of the instance being emitted, by the NodeId of
+    // the type parameter, and by the root of its BoundTy.
+    generics: RefCell<TreeMap<NodeId, Ty>>,
+    subst: RefCell<TreeMap<uint, Ty>>,
+    overflow: OverflowMode,
+    // Whether to check array indices, pointer dereferences, and enum tags.
+    debug_checks: bool,
+    // Whether to compile away the checks of assertions, and trap in
+    // unreachable code without reporting where.
+    release: bool,
+    session: Session,
+    typemap: Typemap,
+}
+
+static C_RESERVED: &'static [&'static str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do",
+    "double", "else", "enum", "extern", "float", "for", "goto", "if",
+    "inline", "int", "long", "register", "restrict", "return", "short",
+    "signed", "sizeof", "static", "struct", "switch", "typedef", "union",
+    "unsigned", "void", "volatile", "while", "_Alignas", "_Alignof",
+    "_Atomic", "_Bool", "_Complex", "_Generic", "_Imaginary", "_Noreturn",
+    "_Static_assert", "_Thread_local", "bool", "true", "false", "NULL",
+    "main", "printf", "fprintf", "fwrite", "stdout", "stderr", "free", "exit", "abort",
+    "wrapping_add", "wrapping_sub", "wrapping_mul",
+    "checked_add", "checked_sub", "checked_mul",
+    "mc_alloc", "mc_free",
+    "int8_t", "int16_t", "int32_t", "uint8_t", "uint16_t", "uint32_t", "size_t",
+    // The rest of <stdio.h>, which a hosted program includes.
+    "FILE", "EOF", "BUFSIZ", "SEEK_SET", "SEEK_CUR", "SEEK_END", "stdin",
+    "fopen", "freopen", "fclose", "fflush", "fread", "fgetc", "fgets", "fputc",
+    "fputs", "getc", "getchar", "gets", "putc", "puts", "putchar", "ungetc",
+    "scanf", "fscanf", "sscanf", "sprintf", "snprintf", "vprintf", "vfprintf",
+    "vsprintf", "vsnprintf", "fseek", "ftell", "rewind", "fgetpos", "fsetpos",
+    "feof", "ferror", "clearerr", "perror", "remove", "rename", "tmpfile",
+    "tmpnam", "setbuf", "setvbuf",
+];
+
+// Make a source identifier safe to use as a C identifier, by prefixing the
+// ones that are C keywords or otherwise reserved.
+fn escape_ident(s: &str) -> String {
+    let reserved = C_RESERVED.contains(&s) ||
+                   s.starts_with("__") ||
+                   // <stdatomic.h> takes all of these.
+                   s.starts_with("atomic_") || s.starts_with("memory_order") ||
+                   (s.len() > 1 && s.starts_with("_") && s.char_at(1).is_uppercase());
+    if reserved {
+        format!("mc_{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+// Walks the whole program, giving every item a C name and collecting the
+// items so they can all be emitted at the top level. An item's C name is
+// made from its path, with components joined by `__`, so `a::Foo::Bar`
+// becomes `a__Foo__Bar`. Items nested in a function body are named as if
+// the function were a module. Any name that's taken already gets a numeric
+// suffix. Extern and exported functions are the exception, as C has to know
+// them by the name they're linked by.
+struct ItemCollector<'a> {
+    session: &'a Session,
+    path: Vec<String>,
+    used: TreeSet<String>,
+    names: TreeMap<NodeId, String>,
+    variants: TreeMap<NodeId, (NodeId, i64)>,
+    items: Vec<Item>,
+    // Whether `main` is just another function, as it is when there's no C
+    // library to call it.
+    freestanding: bool,
+}
+
+impl<'a> ItemCollector<'a> {
+    fn collect(session: &'a Session, module: &Module, freestanding: bool)
+               -> (TreeMap<NodeId, String>, TreeMap<NodeId, (NodeId, u64)>, Vec<Item>,
+                   TreeSet<String>) {
+        // Other items mustn't take the names of the externs and exports,
+        // wherever they are declared.
+        let mut externs = ExternCollector {
+            session: session,
+            used: TreeSet::new(),
+            exports: TreeSet::new(),
+        };
+        externs.visit_module(module);
+        let mut used = externs.used;
+        for s in C_RESERVED.iter() {
+            used.insert(s.to_string());
+        }
+
+        let mut collector = ItemCollector {
+            session: session,
+            path: vec!(),
+            used: used,
+            names: TreeMap::new(),
+            variants: TreeMap::new(),
+            items: vec!(),
+            freestanding: freestanding,
+        };
+        collector.visit_module(module);
+
+        let ItemCollector { names, variants, items, used, .. } = collector;
+        (names, variants, items, used)
+    }
+
+    fn add_name(&mut self, ident: &Ident, is_func: bool) {
+        let session = self.session;
+        let name = session.interner.name_to_str(&ident.val.name);
+
+        // The entry point has to keep the name that C knows it by, unless
+        // there's no C library to call it.
+        let is_entry = self.path.is_empty() && is_func && name == "main";
+        let c_name = if is_entry && !self.freestanding {
+            name.to_string()
+        } else {
+            let mut elems = self.path.clone();
+            elems.push(escape_ident(name));
+            let base = elems.connect("__");
+
+            let mut c_name = base.clone();
+            let mut n = 0u;
+            while self.used.contains(&c_name) {
+                n += 1;
+                c_name = format!("{}_{}", base, n);
+            }
+            c_name
+        };
+
+        self.used.insert(c_name.clone());
+        self.names.insert(ident.id, c_name);
+    }
+
+    fn with_path(&mut self, ident: &Ident, f: |&mut ItemCollector<'a>|) {
+        let session = self.session;
+        let name = session.interner.name_to_str(&ident.val.name);
+        self.path.push(escape_ident(name));
+        f(self);
+        self.path.pop();
+    }
+}
+
+impl<'a> Visitor for ItemCollector<'a> {
+    fn visit_item(&mut self, item: &Item) {
+        match item.val {
+            FuncItem(ref id, _, _, ref block, _) => {
+                match link_name(self.session, item) {
+                    Some(name) => { self.names.insert(id.id, name); }
+                    None => self.add_name(id, true),
+                }
+                self.items.push(item.clone());
+                self.with_path(id, |me| me.visit_block(block));
+            }
+            ExternFuncItem(ref id, _, _, _, _, _) => {
+                let name = link_name(self.session, item).unwrap();
+                self.names.insert(id.id, name);
+                self.items.push(item.clone());
+            }
+            StructItem(ref id, _, _) | UnionItem(ref id, _, _) => {
+                self.add_name(id, false);
+                self.items.push(item.clone());
+            }
+            EnumItem(ref id, ref variants, _) => {
+                self.add_name(id, false);
+                self.with_path(id, |me| {
+                    for (variant, disc) in variants.iter().zip(discriminants(variants).move_iter()) {
+                        me.add_name(&variant.ident, false);
+                        me.variants.insert(variant.ident.id, (id.id, disc));
+                    }
+                });
+                self.items.push(item.clone());
+            }
+            StaticItem(ref id, _, _) => {
+                self.add_name(id, false);
+                self.items.push(item.clone());
+                walk_item(self, item);
+            }
+            ModItem(ref id, ref module) => {
+                self.with_path(id, |me| me.visit_module(module));
+            }
+        }
+    }
+}
+
+// The name a function is linked by, if it's an extern or an export. That's
+// the link name of an extern that has one, and otherwise its own name.
+fn link_name(session: &Session, item: &Item) -> Option<String> {
+    match item.val {
+        ExternFuncItem(_, _, _, _, _, Some(ref link_name)) => Some(link_name.clone()),
+        ExternFuncItem(ref id, _, _, _, _, None) => {
+            Some(session.interner.name_to_str(&id.val.name).to_string())
+        }
+        FuncItem(ref id, _, _, _, _) if session.parser.is_exported(&id.id) => {
+            Some(session.interner.name_to_str(&id.val.name).to_string())
+        }
+        _ => None,
+    }
+}
+
+// Whether a function can't be exported under `name`, because the C code
+// that's emitted uses it already. The allocator is the exception, since a
+// program may bring its own.
+fn is_reserved_link_name(name: &str) -> bool {
+    name != "mc_alloc" && name != "mc_free" && escape_ident(name).as_slice() != name
+}
+
+// Finds the C names of all the extern and exported functions in the
+// program. Externs are only declarations, so they may share a name with
+// each other, or with the export that defines them (as a freestanding
+// program might define memcpy), but no two exports may share one.
+struct ExternCollector<'a> {
+    session: &'a Session,
+    used: TreeSet<String>,
+    exports: TreeSet<String>,
+}
+
+impl<'a> ExternCollector<'a> {
+    fn error(&self, id: &Ident, msg: String) -> ! {
+        let file = self.session.parser.filename_of(&id.id);
+        let pos = self.session.parser.span_of(&id.id).get_begin();
+        fail!("{}:{}:{}: {}", self.session.interner.name_to_str(&file),
+              pos.row + 1, pos.col + 1, msg)
+    }
+}
+
+impl<'a> Visitor for ExternCollector<'a> {
+    fn visit_item(&mut self, item: &Item) {
+        for name in link_name(self.session, item).move_iter() {
+            match item.val {
+                FuncItem(ref id, _, _, _, _) => {
+                    if is_reserved_link_name(name.as_slice()) {
+                        self.error(id, format!("`{}' can't be exported, as the C code \
+                                                uses that name itself", name));
+                    }
+                    if self.exports.contains(&name) {
+                        self.error(id, format!("`{}' is already the name of another \
+                                                exported function", name));
+                    }
+                    self.exports.insert(name.clone());
+                }
+                _ => {}
+            }
+            self.used.insert(name);
+        }
+        walk_item(self, item);
+    }
+}
+
+// The number of bits in an integer type of width `w`. Integers whose width
+// was never pinned down are 32 bits, the same as pointer offsets in the
+// typechecker.
+fn width_bits(w: Width) -> uint {
+    match w {
+        AnyWidth | Width32 => 32,
+        Width16 => 16,
+        Width8 => 8,
+    }
+}
+
+// A C string literal with the same bytes as `s`. Anything that isn't
+// printable ASCII is written as an octal escape, which (unlike a hex escape)
+// can't run on into the character after it.
+fn c_string(s: &str) -> String {
+    let mut res = String::from_str("\"");
+    for &b in s.as_bytes().iter() {
+        match b as char {
+            '"' | '\\' => { res.push_char('\\'); res.push_char(b as char); }
+            ' '..'~' => res.push_char(b as char),
+            _ => res.push_str(format!("\\\\{:03o}", b).as_slice()),
+        }
+    }
+    res.push_char('"');
+    res
+}
+
+fn c_memory_order(order: MemOrder) -> &'static str {
+    match order {
+        Relaxed => "memory_order_relaxed",
+        Acquire => "memory_order_acquire",
+        Release => "memory_order_release",
+        SeqCst  => "memory_order_seq_cst",
+    }
+}
+
+// An asm! template in GCC's syntax, where `{n}` becomes `%m`, with m the
+// position of operand n in `order`, and a literal % is doubled. The parser
+// has already checked the placeholders.
+fn c_asm_template(template: &str, order: &Vec<uint>) -> String {
+    let mut res = String::new();
+    let mut chars = template.chars().peekable();
+    loop {
+        match chars.next() {
+            Some('{') if chars.peek() == Some(&'{') => { chars.next(); res.push_char('{'); }
+            Some('}') if chars.peek() == Some(&'}') => { chars.next(); res.push_char('}'); }
+            Some('{') => {
+                let mut n = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') | None => break,
+                        Some(c) => n.push_char(c),
+                    }
+                }
+                let n: uint = from_str(n.as_slice()).unwrap();
+                let pos = order.iter().position(|&i| i == n).unwrap();
+                res.push_str(format!("%{}", pos).as_slice());
+            }
+            Some('%') => res.push_str("%%"),
+            Some(c) => res.push_char(c),
+            None => break,
+        }
+    }
+    res
+}
+
+// Emit the definition of `name`, after the definitions of everything it
+// depends on.
+fn emit_type_def(name: &String,
+                 defs: &TreeMap<String, (Vec<String>, String)>,
+                 emitted: &mut TreeSet<String>,
+                 out: &mut Vec<String>) {
+    if emitted.contains(name) {
+        return;
+    }
+    emitted.insert(name.clone());
+
+    match defs.find(name) {
+        Some(&(ref deps, ref def)) => {
+            for dep in deps.iter() {
+                emit_type_def(dep, defs, emitted, out);
+            }
+            out.push(def.clone());
+        }
+        None => {}
+    }
+}
+
+// How an enum is laid out in C.
+enum EnumLayout {
+    // A tag of this integer type says which variant the value is, and the
+    // variants' fields share a union.
+    TaggedLayout(Ty),
+    // There's no tag. Only one variant, the one at the first index, has
+    // fields, and the others are told apart from it by invalid values of
+    // its field at the second index: the first one of them stands for the
+    // first of the others, and so on.
+    NicheLayout(uint, uint, Niche),
+}
+
+// Values that a value of some type never holds, which an enum can store
+// there to stand for its variants without fields.
+struct Niche {
+    // Where they go, as C member accesses from the value.
+    path: String,
+    // The first one, and how many of them there are.
+    start: u64,
+    count: u64,
+}
+
+impl CCrossCompiler {
+    fn visit_list<T>(&self, list: &Vec<T>,
+                            visit: |&T| -> String,
+                            delimiter: &str) -> String {
+        let list: Vec<String> = list.iter().map(visit).collect();
+        list.connect(format!("{}\n", delimiter).as_slice())
+    }
+
+    fn visit_binop(&self, op: &BinOp) -> String {
+        format!("{}", op)
+    }
+
+    fn visit_unop(&self, op: &UnOp) -> String {
+        format!("{}", op)
+    }
+
+    // A block, as an expression.
+    fn visit_block_expr(&self, block: &Block) -> String {
+        self.visit_block(block, |e| e.map(|e| format!("{};", e)).unwrap_or_default())
+    }
+
+    fn visit_name_and_type(&self, name: &str, t: &Type) -> String {
+        match t.val {
+            // We have to special case this, because of the way things of
+            // a function pointer type are declared in C.
+            FuncType(ref d, ref r) => {
+                let ty = self.visit_type(*r);
+                let list = self.visit_list(d, |x| self.visit_type(x), ", ");
+                format!("{}(*{})({})", ty, name, list)
+            },
+            ArrayType(ref t, ref size) => {
+                format!("{} {}[{}]", self.visit_type(*t), name, *size)
+            },
+            _ => {
+                let ty = self.visit_type(t);
+                format!("{} {}", ty, name)
+            }
+        }
+    }
+
+    // The C declaration of a variable bound by an IdentPat.
+    fn visit_binding(&self, pat: &Pat, ident: &Ident, t: &Option<Type>) -> String {
+        match *t {
+            Some(ref ty) => self.visit_name_and_type(self.visit_ident(ident).as_slice(), ty),
+            None => {
+                let ty = self.visit_ty(self.typemap.types.get(&pat.id.to_uint()));
+                format!("{} {}", ty, self.visit_ident(ident))
+            }
+        }
+    }
+
+    // Declare every variable bound by an irrefutable pattern. If `src` is
+    // given, it's a C expression for the value being matched, and each
+    // variable is initialized from the corresponding part of it.
+    fn visit_pat_bindings(&self, pat: &Pat, src: &Option<String>) -> String {
+        match pat.val {
+            DiscardPat(..) => String::new(),
+            IdentPat(ref i, ref t) => {
+                let init = src.as_ref().map(|s| format!(" = {}", s)).unwrap_or_default();
+                format!("{}{};", self.visit_binding(pat, i, t), init)
+            }
+            TuplePat(ref pats) => {
+                let mut n = 0;
+                self.visit_list(pats, |p| {
+                    n += 1;
+                    let field = src.as_ref().map(|s| format!("{}.field{}", s, n - 1));
+                    self.visit_pat_bindings(p, &field)
+                }, " ")
+            }
+            StructPat(_, ref fps) => {
+                self.visit_list(fps, |fp| {
+                    let name = self.visit_name(&fp.name);
+                    let field = src.as_ref().map(|s| format!("{}.{}", s, name));
+                    self.visit_pat_bindings(&fp.pat, &field)
+                }, " ")
+            }
+            VariantPat(..) => fail!("Refutable patterns are not allowed in let statements"),
+        }
+    }
+
+    fn visit_stmt(&self, stmt: &Stmt) -> String {
+        match stmt.val {
+            LetStmt(ref pat, ref e) => {
+                let init = e.as_ref().map(|e| self.visit_init(e));
+                match pat.val {
+                    IdentPat(..) => self.visit_pat_bindings(pat, &init),
+                    _ => {
+                        // Evaluate the initializer exactly once, into a
+                        // temporary, and pick the bindings out of that.
+                        match init {
+                            Some(init) => {
+                                let tmp = format!("__pat{}", pat.id.to_uint());
+                                let ty = self.visit_ty(self.typemap.types.get(&pat.id.to_uint()));
+                                let bindings = self.visit_pat_bindings(pat, &Some(tmp.clone()));
+                                format!("{} {} = {}; {}", ty, tmp, init, bindings)
+                            }
+                            None => self.visit_pat_bindings(pat, &None),
+                        }
+                    }
+                }
+            },
+            ExprStmt(ref e) | SemiStmt(ref e) => { format!("{};", self.visit_expr(e)) },
+        }
+    }
+
+    // The initializer of a variable. C arrays can't be assigned, so array
+    // literals are only allowed here, as braced initializers.
+    fn visit_init(&self, expr: &Expr) -> String {
+        match expr.val {
+            ArrayExpr(ref es) => {
+                let es = self.visit_list(es, |e| self.visit_init(e), ", ");
+                format!("\\{ {} \\}", es)
+            }
+            _ => self.visit_expr(expr),
+        }
+    }
+
+    // Any items in the block were hoisted to the top level by ItemCollector,
+    // so they aren't emitted here.
+    fn visit_block(&self, block: &Block, tail: |Option<String>| -> String) -> String {
+        let stmts = self.visit_list(&block.stmts, |t| self.visit_stmt(t), "; ");
+        let expr = match block.expr {
+            Some(ref x) => {
+                match x.val {
+                    WhileExpr(..) | ForExpr(..) => self.visit_expr(x),
+                    ReturnExpr(ref e) => tail(Some(self.visit_expr(*e))),
+                    _ => tail(Some(self.visit_expr(x))),
+                }
+            }
+            None => tail(None),
+        };
+        format!("\\{ {} {} \\}", stmts, expr)
+    }
+
+    // Statics are initialized with their values, which `consts` works out.
+    fn visit_item(&self, item: &Item, consts: &ConstEvaluator) -> String {
+        match item.val {
+            FuncItem(ref name, ref args, ref t, ref block, ref tps) => {
+                // Generic functions are emitted once for each instance.
+                if !tps.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}{}", self.linkage(name),
+                            self.visit_func(self.item_name(name), args, t, block))
+                }
+            }
+            // These are emitted by visit_type_defs, and externs only need
+            // the prototypes from visit_module.
+            StructItem(..) | UnionItem(..) | EnumItem(..) | ExternFuncItem(..) => String::new(),
+            StaticItem(ref id, ref ty, _) => {
+                let name_and_type = self.visit_static_decl(id, ty);
+                match consts.eval_static(id.id) {
+                    Some(value) => format!("static {} = {};",
+                                           name_and_type,
+                                           self.visit_const(&value)),
+                    None => format!("static {};", name_and_type)
+                }
+            }
+            // The items inside were hoisted out by ItemCollector.
+            ModItem(..) => String::new(),
+        }
+    }
+
+    fn visit_static_decl(&self, id: &Ident, ty: &Option<Type>) -> String {
+        let name = self.item_name(id);
+        match *ty {
+            Some(ref t) => self.visit_name_and_type(name, t),
+            // The typechecker records the type of the static under its
+            // ident.
+            None => {
+                let t = self.typemap.types.get(&id.id.to_uint());
+                format!("{} {}", self.visit_ty(t), name)
+            }
+        }
+    }
+
+    // A value worked out by ConstEvaluator, as a C initializer. Aggregates
+    // become plain braced initializers, since C requires those to be
+    // constant.
+    fn visit_const(&self, value: &Const) -> String {
+        match *value {
+            LitConst(ref lit @ NumLit(..)) => format!("{}", int_value(lit)),
+            LitConst(BoolLit(b)) => String::from_str(if b { "true" } else { "false" }),
+            LitConst(StringLit(ref s)) => {
+                format!("\\{ (const unsigned char *){}, {} \\}",
+                        c_string(s.as_slice()), s.len())
+            }
+            LitConst(NullLit) => String::from_str("NULL"),
+            TupleConst(ref vs) | ArrayConst(ref vs) => {
+                let vs = self.visit_list(vs, |v| self.visit_const(v), ", ");
+                format!("\\{ {} \\}", vs)
+            }
+            StructConst(ref flds) => {
+                let flds = self.visit_list(flds, |&(ref name, ref v)| {
+                    format!(".{} = {}", self.visit_name(name), self.visit_const(v))
+                }, ", ");
+                format!("\\{ {} \\}", flds)
+            }
+            VariantConst(did, ref args, ref t) => {
+                let args = args.iter().map(|v| self.visit_const(v)).collect();
+                format!("\\{ {} \\}", self.visit_variant_init(t, did, args))
+            }
+            AddrConst(did) => {
+                self.note_use(did);
+                format!("&{}", self.names.find(&did).unwrap())
+            }
+            FuncConst(did) => {
+                self.note_use(did);
+                self.names.find(&did).unwrap().clone()
+            }
+            PtrCastConst(ref v, ref t) => {
+                let inner = match **v {
+                    // Just the characters, not the str.
+                    LitConst(StringLit(ref s)) => c_string(s.as_slice()),
+                    ref v => self.visit_const(v),
+                };
+                format!("(({}){})", self.visit_ty(t), inner)
+            }
+        }
+    }
+
+    fn visit_func(&self, name: &str, args: &Vec<FuncArg>, t: &Type, block: &Block) -> String {
+        let sig = self.visit_func_sig(name, args, t);
+        // A tail expression that diverges is void in C, so it can't be
+        // returned.
+        let diverges = match block.expr {
+            Some(WithId { val: ReturnExpr(..), .. }) | None => false,
+            Some(ref e) => self.diverges(e),
+        };
+        let entry = self.is_unit_entry(name, t);
+        self.in_entry.set(entry);
+        let block = self.visit_block(block, |e| {
+            match e {
+                Some(e) if diverges => format!("{};", e),
+                Some(e) if entry => format!("{}; return 0;", e),
+                Some(e) => format!("return {};", e),
+                None if entry => String::from_str("return 0;"),
+                None => String::from_str("return;"),
+            }
+        });
+        self.in_entry.set(false);
+
+        format!("{} {}", sig, block)
+    }
+
+    // Whether this is an entry point that C runs, but that returns
+    // nothing. C wants an int, so it returns 0 for success.
+    fn is_unit_entry(&self, name: &str, t: &Type) -> bool {
+        name == "main" && self.type_to_ty(t) == UnitTy
+    }
+
+    // Only the entry point and exported functions are visible outside the
+    // C file, so that separately compiled programs can't clash.
+    fn linkage(&self, name: &Ident) -> &'static str {
+        if self.item_name(name) == "main" || self.session.parser.is_exported(&name.id) {
+            ""
+        } else {
+            "static "
+        }
+    }
+
+    fn visit_func_sig(&self, name: &str, args: &Vec<FuncArg>, t: &Type) -> String {
+        if self.is_unit_entry(name, t) {
+            return String::from_str("int main(void)");
+        }
+        let ty = self.visit_type(t);
+        let args = self.visit_list(args, |x| self.visit_func_arg(x), ", ");
+        format!("{} {}({})", ty, name, args)
+    }
+
+    fn visit_func_arg(&self, arg: &FuncArg) -> String {
+        self.visit_name_and_type(self.visit_ident(&arg.ident).as_slice(), &arg.argtype)
+    }
+
+    fn visit_type(&self, t: &Type) -> String {
+        self.visit_ty(&self.type_to_ty(t))
+    }
+
+    // Convert an AST type into a Ty, so that types written in the source
+    // and types inferred by the typechecker are emitted the same way.
+    fn type_to_ty(&self, t: &Type) -> Ty {
+        match t.val {
+            BoolType => BoolTy,
+            StrType => StrTy,
+            UnitType => UnitTy,
+            IntType(GenericInt) => GenericIntTy,
+            IntType(SignedInt(w)) => IntTy(w),
+            IntType(UnsignedInt(w)) => UintTy(w),
+            PtrType(ref t, volatile) => PtrTy(box self.type_to_ty(*t), volatile),
+            NamedType(ref path) => {
+                let did = self.session.resolver.def_from_path(path);
+                let tps = match path.val.elems.last().unwrap().val.tps {
+                    Some(ref tps) => tps.iter().map(|t| self.type_to_ty(t)).collect(),
+                    None => vec!(),
+                };
+                match *self.session.defmap.find(&did).take_unwrap() {
+                    StructDef(..) | UnionDef(..) => StructTy(did, tps),
+                    EnumDef(..) => EnumTy(did, tps),
+                    TypeDef(ref t) => self.type_to_ty(t),
+                    GenericDef => match self.generics.borrow().find(&did) {
+                        Some(t) => t.clone(),
+                        None => fail!("Type parameter {} used outside of an instance", path),
+                    },
+                    _ => fail!("{} does not name a type", path),
+                }
+            }
+            FuncType(ref d, ref r) => {
+                let args = d.iter().map(|t| self.type_to_ty(t)).collect();
+                FuncTy(args, box self.type_to_ty(*r))
+            }
+            ArrayType(ref t, len) => ArrayTy(box self.type_to_ty(*t), Some(len)),
+            TupleType(ref ts) => TupleTy(ts.iter().map(|t| self.type_to_ty(t)).collect()),
+        }
+    }
+
+    // Look through any BoundTys in a type. Returns None if some part of the
+    // type was never pinned down by the typechecker (and isn't a type
+    // parameter of the instance being emitted).
+    fn resolve_ty(&self, t: &Ty) -> Option<Ty> {
+        let resolve_all = |ts: &Vec<Ty>| -> Option<Vec<Ty>> {
+            let ts: Vec<Option<Ty>> = ts.iter().map(|t| self.resolve_ty(t)).collect();
+            if ts.iter().all(|t| t.is_some()) {
+                Some(ts.move_iter().map(|t| t.unwrap()).collect())
+            } else {
+                None
+            }
+        };
+
+        match *t {
+            BoundTy(bid) => match *self.typemap.find_bounds(bid) {
+                Concrete(ref t) => self.resolve_ty(t),
+                _ => {
+                    let root = self.typemap.root(bid);
+                    self.subst.borrow().find(&root.to_uint()).map(|t| t.clone())
+                }
+            },
+            PtrTy(ref t, volatile) => self.resolve_ty(*t).map(|t| PtrTy(box t, volatile)),
+            ArrayTy(ref t, len) => self.resolve_ty(*t).map(|t| ArrayTy(box t, len)),
+            TupleTy(ref ts) => resolve_all(ts).map(|ts| TupleTy(ts)),
+            FuncTy(ref args, ref r) => {
+                resolve_all(args).and_then(|args| {
+                    self.resolve_ty(*r).map(|r| FuncTy(args, box r))
+                })
+            }
+            StructTy(nid, ref ts) => resolve_all(ts).map(|ts| StructTy(nid, ts)),
+            EnumTy(nid, ref ts) => resolve_all(ts).map(|ts| EnumTy(nid, ts)),
+            ref t => Some(t.clone()),
+        }
+    }
+
+    fn resolve_ty_or_fail(&self, t: &Ty) -> Ty {
+        match self.resolve_ty(t) {
+            Some(t) => t,
+            None => fail!("Type is not fully constrained: {}", t),
+        }
+    }
+
+    // Run `f` with the type parameters `tps` standing for `args`, so that
+    // everything emitted is specialised to that instance.
+    fn with_instance<T>(&self, tps: &Vec<Ident>, args: &Vec<Ty>, f: || -> T) -> T {
+        let mut generics = TreeMap::new();
+        let mut subst = TreeMap::new();
+        for (tp, arg) in tps.iter().zip(args.iter()) {
+            generics.insert(tp.id, arg.clone());
+            match self.typemap.types.find(&tp.id.to_uint()) {
+                Some(&BoundTy(bid)) => {
+                    subst.insert(self.typemap.root(bid).to_uint(), arg.clone());
+                }
+                _ => {}
+            }
+        }
+
+        let old_generics = replace(&mut *self.generics.borrow_mut(), generics);
+        let old_subst = replace(&mut *self.subst.borrow_mut(), subst);
+        let ret = f();
+        *self.generics.borrow_mut() = old_generics;
+        *self.subst.borrow_mut() = old_subst;
+        ret
+    }
+
+    fn mangle_list(&self, ts: &Vec<Ty>) -> String {
+        let ts: Vec<String> = ts.iter().map(|t| self.mangle_ty(t)).collect();
+        ts.connect("_")
+    }
+
+    // A name for a type that's usable as part of a C identifier. Tuple
+    // structs are named after their element types, so equal tuple types
+    // always get the same C type.
+    fn mangle_ty(&self, t: &Ty) -> String {
+        match *t {
+            BoolTy => String::from_str("bool"),
+            UnitTy => String::from_str("unit"),
+            StrTy => String::from_str("str"),
+            GenericIntTy => String::from_str("int"),
+            IntTy(w) => format!("i{}", w),
+            UintTy(w) => format!("u{}", w),
+            PtrTy(ref t, false) => format!("ptr_{}", self.mangle_ty(*t)),
+            PtrTy(ref t, true) => format!("vptr_{}", self.mangle_ty(*t)),
+            ArrayTy(ref t, Some(len)) => format!("arr{}_{}", len, self.mangle_ty(*t)),
+            ArrayTy(ref t, None) => format!("arr_{}", self.mangle_ty(*t)),
+            FuncTy(ref args, ref r) => format!("fn{}_{}_{}", args.len(), self.mangle_list(args),
+                                               self.mangle_ty(*r)),
+            TupleTy(..) | StructTy(..) | EnumTy(..) => self.aggregate_name(t),
+            BoundTy(..) => self.mangle_ty(&self.resolve_ty_or_fail(t)),
+            BottomTy => fail!("Cannot mangle the bottom type"),
+        }
+    }
+
+    // The C name for an instance of a generic item. Items without type
+    // parameters just keep their own name.
+    fn instance_name(&self, nid: NodeId, args: &Vec<Ty>) -> String {
+        let base = self.names.find(&nid).unwrap();
+        if args.is_empty() {
+            return base.clone();
+        }
+
+        let mangled = self.mangle_list(args);
+        let key = (nid, mangled.clone());
+        match self.instances.borrow().find(&key) {
+            Some(name) => return name.clone(),
+            None => {}
+        }
+
+        let base = format!("{}__{}", base, mangled);
+        let mut name = base.clone();
+        let mut n = 0u;
+        while self.used.borrow().contains(&name) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+
+        self.used.borrow_mut().insert(name.clone());
+        self.instances.borrow_mut().insert(key, name.clone());
+        name
+    }
+
+    // The C name of the instance of a generic function that `path` refers
+    // to. The first time an instance comes up, it's queued to be emitted.
+    fn fn_instance_name(&self, did: NodeId, path: &Path) -> String {
+        let args: Vec<Ty> = self.typemap.type_args.get(&path.id.to_uint()).iter()
+            .map(|t| self.resolve_ty_or_fail(t)).collect();
+        let is_new = !self.instances.borrow().contains_key(&(did, self.mangle_list(&args)));
+        let name = self.instance_name(did, &args);
+        if is_new {
+            self.fn_queue.borrow_mut().push((did, args, name.clone()));
+        }
+        name
+    }
+
+    // The C name of a struct, enum, or tuple type. Each one that comes up
+    // is noted, so that visit_type_defs can emit its definition.
+    fn aggregate_name(&self, t: &Ty) -> String {
+        let t = self.resolve_ty_or_fail(t);
+        let name = match t {
+            TupleTy(ref ts) => format!("tuple{}_{}", ts.len(), self.mangle_list(ts)),
+            StructTy(nid, ref args) | EnumTy(nid, ref args) => self.instance_name(nid, args),
+            _ => unreachable!(),
+        };
+
+        if !self.aggregates.borrow().contains_key(&name) {
+            self.aggregates.borrow_mut().insert(name.clone(), t);
+        }
+        name
+    }
+
+    // The names of the C types that `t` contains by value, and which must
+    // therefore be defined before it is used.
+    fn ty_deps(&self, t: &Ty, deps: &mut Vec<String>) {
+        match *t {
+            ArrayTy(ref t, _) => self.ty_deps(*t, deps),
+            StructTy(..) | EnumTy(..) | TupleTy(..) => deps.push(self.mangle_ty(t)),
+            BoundTy(..) => match self.resolve_ty(t) {
+                Some(t) => self.ty_deps(&t, deps),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    // The item that defines `nid`.
+    fn find_item<'a>(&'a self, nid: NodeId) -> &'a Item {
+        self.items.iter().find(|item| match item.val {
+            FuncItem(ref id, _, _, _, _) | ExternFuncItem(ref id, _, _, _, _, _) |
+            StructItem(ref id, _, _) | UnionItem(ref id, _, _) | EnumItem(ref id, _, _) |
+            StaticItem(ref id, _, _) => id.id == nid,
+            ModItem(..) => false,
+        }).unwrap()
+    }
+
+    // The C definition of a struct, enum or tuple type (which is an instance
+    // of the corresponding item, for generic ones), along with the names of
+    // the types it depends on.
+    fn visit_aggregate_def(&self, name: &String, t: &Ty) -> (Vec<String>, String) {
+        let mut deps = vec!();
+        let def = match *t {
+            TupleTy(ref ts) => {
+                let mut n = 0;
+                let fields = self.visit_list(ts, |t| {
+                    n += 1;
+                    self.ty_deps(t, &mut deps);
+                    format!("{} field{};", self.visit_ty(t), n - 1)
+                }, "\n    ");
+                format!("struct {} \\{\n    {}\n\\};", name, fields)
+            }
+            StructTy(nid, ref args) => {
+                let item = self.find_item(nid);
+                match item.val {
+                    StructItem(_, ref fields, ref tps) |
+                    UnionItem(_, ref fields, ref tps) => self.with_instance(tps, args, || {
+                        let fields = self.visit_list(fields, |field| {
+                            self.ty_deps(&self.type_to_ty(&field.fldtype), &mut deps);
+                            let name = self.visit_name(&field.name);
+                            format!("{};", self.visit_name_and_type(name.as_slice(), &field.fldtype))
+                        }, "\n    ");
+                        format!("{} {} \\{\n    {}\n\\}{};", self.aggregate_keyword(t), name,
+                                fields, self.visit_layout_attrs(&item.id))
+                    }),
+                    _ => unreachable!(),
+                }
+            }
+            EnumTy(nid, ref args) => {
+                let item = self.find_item(nid);
+                match item.val {
+                    EnumItem(_, ref variants, ref tps) => {
+                        let layout = self.enum_layout(t);
+                        self.with_instance(tps, args, || {
+                            let mut v = 0;
+                            let variants = self.visit_list(variants, |variant| {
+                                v += 1;
+                                let mut n = 0;
+                                let fields = self.visit_list(&variant.args, |t| {
+                                    n += 1;
+                                    self.ty_deps(&self.type_to_ty(t), &mut deps);
+                                    match layout {
+                                        // A bool that holds a niche is
+                                        // stored as a byte, which can hold
+                                        // the values a C bool can't.
+                                        NicheLayout(d, f, _) if d == v - 1 && f == n - 1 &&
+                                                                self.type_to_ty(t) == BoolTy => {
+                                            format!("uint8_t field{};", n - 1)
+                                        }
+                                        _ => format!("{} field{};", self.visit_type(t), n - 1),
+                                    }
+                                }, "\n        ");
+                                let name = self.visit_ident(&variant.ident);
+                                format!("struct \\{ {} \\} {};", fields, name)
+                            }, "\n");
+                            let tag = match layout {
+                                TaggedLayout(ref tag) => format!("\n    {} tag;", self.visit_ty(tag)),
+                                NicheLayout(..) => String::new(),
+                            };
+                            format!("struct {} \\{{}\n    union \\{\n        {}\n    \\} val;\n\\};",
+                                    name, tag, variants)
+                        })
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        (deps, def)
+    }
+
+    // The discriminant of each variant of an enum type.
+    fn enum_discs(&self, t: &Ty) -> Vec<i64> {
+        match self.resolve_ty_or_fail(t) {
+            EnumTy(nid, _) => match self.find_item(nid).val {
+                EnumItem(_, ref variants, _) => discriminants(variants),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Enums get the smallest layout they can. One that has a `#[repr]` keeps
+    // the tag it asked for. One where only a single variant has fields, and
+    // one of those fields has enough invalid values for the other variants,
+    // keeps them there, and has no tag, so that `Option<bool>` is the size of
+    // a bool. Anything else gets a tag that's just big enough.
+    fn enum_layout(&self, t: &Ty) -> EnumLayout {
+        let (nid, args) = match self.resolve_ty_or_fail(t) {
+            EnumTy(nid, args) => (nid, args),
+            _ => unreachable!(),
+        };
+        let item = self.find_item(nid);
+        let (variants, tps) = match item.val {
+            EnumItem(_, ref variants, ref tps) => (variants, tps),
+            _ => unreachable!(),
+        };
+
+        if self.session.parser.find_attr(&item.id, "repr").is_some() {
+            return TaggedLayout(match self.session.parser.enum_tag_kind(&item.id) {
+                SignedInt(w) => IntTy(w),
+                UnsignedInt(w) => UintTy(w),
+                GenericInt => unreachable!(),
+            });
+        }
+
+        let dataful: Vec<uint> = range(0, variants.len())
+            .filter(|&i| !variants.get(i).args.is_empty()).collect();
+        if dataful.len() == 1 && variants.len() > 1 {
+            let d = *dataful.get(0);
+            let variant = variants.get(d);
+            let fields: Vec<Ty> = self.with_instance(tps, &args, || {
+                variant.args.iter().map(|t| self.resolve_ty_or_fail(&self.type_to_ty(t))).collect()
+            });
+            let needed = variants.len() as u64 - 1;
+            for (f, field) in fields.iter().enumerate() {
+                match self.niche_of(field) {
+                    Some(ref niche) if niche.count >= needed => {
+                        let path = format!(".val.{}.field{}{}", self.visit_ident(&variant.ident),
+                                           f, niche.path);
+                        return NicheLayout(d, f, Niche { path: path, start: niche.start,
+                                                         count: niche.count });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let discs = discriminants(variants);
+        let min = discs.iter().map(|d| *d).min().unwrap_or(0);
+        let max = discs.iter().map(|d| *d).max().unwrap_or(0);
+        TaggedLayout(if min < 0 {
+            if min >= -0x80 && max < 0x80 {
+                IntTy(Width8)
+            } else if min >= -0x8000 && max < 0x8000 {
+                IntTy(Width16)
+            } else {
+                IntTy(Width32)
+            }
+        } else if max <= 0xff {
+            UintTy(Width8)
+        } else if max <= 0xffff {
+            UintTy(Width16)
+        } else {
+            UintTy(Width32)
+        })
+    }
+
+    // The values a value of type `t` never holds, if there are any that an
+    // enum can use: null for functions, anything but 0 and 1 for bools, and
+    // the tags of an enum that are past all its variants'. Pointers have
+    // none, since null is as good a pointer as any other.
+    fn niche_of(&self, t: &Ty) -> Option<Niche> {
+        match *t {
+            FuncTy(..) => Some(Niche { path: String::new(), start: 0, count: 1 }),
+            BoolTy => Some(Niche { path: String::new(), start: 2, count: 254 }),
+            EnumTy(..) => {
+                let discs = self.enum_discs(t);
+                match self.enum_layout(t) {
+                    TaggedLayout(tag) => {
+                        let top = match tag {
+                            IntTy(w) => (1i64 << (width_bits(w) - 1)) - 1,
+                            UintTy(w) => (1i64 << width_bits(w)) - 1,
+                            _ => unreachable!(),
+                        };
+                        // Only the tags that aren't negative, so that
+                        // the niche's values are too.
+                        let start = ::std::cmp::max(discs.move_iter().max().unwrap_or(0) + 1, 0);
+                        if start <= top {
+                            Some(Niche { path: String::from_str(".tag"), start: start as u64,
+                                         count: (top - start + 1) as u64 })
+                        } else {
+                            None
+                        }
+                    }
+                    // Whatever the enum's own variants left over.
+                    NicheLayout(_, _, niche) => {
+                        let used = discs.len() as u64 - 1;
+                        if niche.count > used {
+                            Some(Niche { path: niche.path, start: niche.start + used,
+                                         count: niche.count - used })
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // The designated initializers for the variant `did` of the enum type
+    // `t`, with `args` as its fields.
+    fn visit_variant_init(&self, t: &Ty, did: NodeId, args: Vec<String>) -> String {
+        let t = self.resolve_ty_or_fail(t);
+        let (nid, _) = *self.variants.find(&did).unwrap();
+        let variants = match self.find_item(nid).val {
+            EnumItem(_, ref variants, _) => variants,
+            _ => unreachable!(),
+        };
+        let v = variants.iter().position(|v| v.ident.id == did).unwrap();
+        let name = self.visit_ident(&variants.get(v).ident);
+        let fields: Vec<String> = args.iter().enumerate().map(|(n, arg)| {
+            format!(".val.{}.field{} = {}", name, n, arg)
+        }).collect();
+
+        match self.enum_layout(&t) {
+            TaggedLayout(_) => {
+                let disc = *self.enum_discs(&t).get(v);
+                let mut inits = vec!(format!(".tag = {}", disc));
+                inits.push_all_move(fields);
+                inits.connect(", ")
+            }
+            NicheLayout(d, _, _) if d == v => fields.connect(", "),
+            // The variants without fields come in order, skipping the one
+            // with them.
+            NicheLayout(d, _, niche) => {
+                let k = if v < d { v } else { v - 1 };
+                format!("{} = {}", niche.path, niche.start + k as u64)
+            }
+        }
+    }
+
+    // The discriminant of `value`, a C expression of the enum type `t`.
+    fn visit_tag(&self, value: &str, t: &Ty, id: uint) -> String {
+        match self.enum_layout(t) {
+            TaggedLayout(_) => format!("({}).tag", value),
+            NicheLayout(d, _, niche) => {
+                let discs = self.enum_discs(t);
+                let slot = format!("__niche{}", id);
+                let mut k = 0;
+                let mut tests = String::new();
+                for (v, disc) in discs.iter().enumerate() {
+                    if v != d {
+                        tests.push_str(format!("{} == {} ? {} : ", slot, niche.start + k, disc)
+                                       .as_slice());
+                        k += 1;
+                    }
+                }
+                format!("(\\{ __typeof__(({}){}) {} = ({}){}; {}{}; \\})",
+                        value, niche.path, slot, value, niche.path, tests, discs.get(d))
+            }
+        }
+    }
+
+    // Unions are the only aggregates that aren't C structs.
+    fn aggregate_keyword(&self, t: &Ty) -> &'static str {
+        match *t {
+            StructTy(nid, _) => match self.find_item(nid).val {
+                UnionItem(..) => "union",
+                _ => "struct",
+            },
+            _ => "struct",
+        }
+    }
+
+    // The GCC attributes for a struct's layout attributes. Structs are laid
+    // out as C lays them out already, so #[repr(C)] needs nothing.
+    fn visit_layout_attrs(&self, item_id: &NodeId) -> String {
+        let mut res = String::new();
+        for attrs in self.session.parser.attrs_of(item_id).iter() {
+            for attr in attrs.iter() {
+                match attr.val {
+                    WordAttr(ref name) if name.as_slice() == "packed" =>
+                        res.push_str(" __attribute__((packed))"),
+                    ListAttr(ref name, ref args) if name.as_slice() == "align" =>
+                        res.push_str(format!(" __attribute__((aligned({})))",
+                                             args.get(0)).as_slice()),
+                    _ => {}
+                }
+            }
+        }
+        res
+    }
+
+    // The definitions of all the struct, enum, and tuple types in the
+    // program. Every type gets a forward typedef, and then the definitions
+    // follow in an order where each type comes after the ones it contains.
+    // This has to run after everything else has been emitted, so that all
+    // the types that were used are known.
+    fn visit_type_defs(&self) -> String {
+        // Every non-generic struct and enum is defined, even if it's unused.
+        for item in self.items.iter() {
+            match item.val {
+                StructItem(ref id, _, ref tps) |
+                UnionItem(ref id, _, ref tps) if tps.is_empty() => {
+                    self.aggregate_name(&StructTy(id.id, vec!()));
+                }
+                EnumItem(ref id, _, ref tps) if tps.is_empty() => {
+                    self.aggregate_name(&EnumTy(id.id, vec!()));
+                }
+                _ => {}
+            }
+        }
+
+        self.visit_used_type_defs()
+    }
+
+    // The definitions of just the types that have been used so far, and
+    // the ones they depend on.
+    fn visit_used_type_defs(&self) -> String {
+        let mut order = vec!();
+        let mut defs = TreeMap::new();
+
+        // Defining one type can turn up others that need defining.
+        loop {
+            let todo: Vec<(String, Ty)> = self.aggregates.borrow().iter()
+                .filter(|&(name, _)| !defs.contains_key(name))
+                .map(|(name, t)| (name.clone(), t.clone()))
+                .collect();
+            if todo.is_empty() {
+                break;
+            }
+
+            for (name, t) in todo.move_iter() {
+                let def = self.visit_aggregate_def(&name, &t);
+                order.push(name.clone());
+                defs.insert(name, def);
+            }
+        }
+
+        let mut out: Vec<String> = order.iter()
+            .map(|name| {
+                let keyword = self.aggregate_keyword(self.aggregates.borrow().find(name).unwrap());
+                format!("typedef {} {} {};", keyword, name, name)
+            })
+            .collect();
+        let mut emitted = TreeSet::new();
+        for name in order.iter() {
+            emit_type_def(name, &defs, &mut emitted, &mut out);
+        }
+
+        out.connect("\n")
+    }
+
+    // The type of an expression, if it's an integer.
+    fn int_ty(&self, expr: &Expr) -> Option<Ty> {
+        self.resolve_ty(self.typemap.types.get(&expr.id.to_uint())).and_then(|t| {
+            match t {
+                GenericIntTy | IntTy(..) | UintTy(..) => Some(t),
+                _ => None,
+            }
+        })
+    }
+
+    // Whether the program defines the exported function `name` itself.
+    fn defines_export(&self, name: &str) -> bool {
+        self.items.iter().any(|item| match item.val {
+            FuncItem(..) => link_name(&self.session, item).map_or(false, |n| n.as_slice() == name),
+            _ => false,
+        })
+    }
+
+    // Whether the expression given to a `new` is the type to allocate.
+    fn names_new_type(&self, e: &Expr) -> bool {
+        match e.val {
+            PathExpr(ref path) => self.session.resolver.is_new_type(path),
+            _ => false,
+        }
+    }
+
+    // A call to the runtime's trap handler, reporting where `expr` is.
+    fn visit_trap(&self, msg: &str, expr: &Expr) -> String {
+        format!("__mc_trap(\"{}\", {})", msg, self.visit_pos(&expr.id))
+    }
+
+    fn visit_asm_operand(&self, op: &AsmOperand) -> String {
+        let prefix = match op.kind {
+            AsmIn => "",
+            AsmOut => "=",
+            AsmInOut => "+",
+        };
+        let constraint = format!("{}{}", prefix, op.constraint);
+        format!("{}({})", c_string(constraint.as_slice()), self.visit_expr(&op.expr))
+    }
+
+    // Where a node is in the source, as a C string.
+    fn visit_pos(&self, id: &NodeId) -> String {
+        let file = self.session.parser.filename_of(id);
+        let pos = self.session.parser.span_of(id).get_begin();
+        c_string(format!("{}:{}:{}", self.session.interner.name_to_str(&file),
+                         pos.row + 1, pos.col + 1).as_slice())
+    }
+
+    // Does evaluating this expression never finish? Those have type void
+    // in C, so they can't be used as values.
+    fn diverges(&self, e: &Expr) -> bool {
+        match self.resolve_ty(self.typemap.types.get(&e.id.to_uint())) {
+            Some(BottomTy) => true,
+            _ => false,
+        }
+    }
+
+    // A branch of an if that's emitted as a statement. Its value, if it has
+    // one, is stored in `tmp`.
+    fn visit_branch(&self, b: &Block, tmp: &Option<String>) -> String {
+        let (is_return, diverges) = match b.expr {
+            Some(ref e) => (match e.val { ReturnExpr(..) => true, _ => false }, self.diverges(e)),
+            None => (false, false),
+        };
+        self.visit_block(b, |e| {
+            match (e, tmp) {
+                (Some(e), _) if is_return => format!("return {};", e),
+                (Some(e), &Some(ref tmp)) if !diverges => format!("{} = {};", tmp, e),
+                (Some(e), _) => format!("{};", e),
+                (None, _) => String::new(),
+            }
+        })
+    }
+
+    // A pointer expression, which with --debug-checks traps if it's null.
+    fn visit_nonnull(&self, e: &Expr) -> String {
+        let ptr = self.visit_expr(e);
+        if self.debug_checks {
+            format!("__mc_check_null(({}), {})", ptr, self.visit_pos(&e.id))
+        } else {
+            ptr
+        }
+    }
+
+    // An integer operation that traps if it overflows, or if it's undefined,
+    // for --overflow-checks. None for operators that can't overflow.
+    fn visit_checked_binop(&self, op: &BinOpNode, t: &Ty, lhs: &str, rhs: &str,
+                           expr: &Expr) -> Option<String> {
+        let ty = self.visit_ty(t);
+        let (signed, bits) = match *t {
+            GenericIntTy => (true, 32),
+            IntTy(w) => (true, width_bits(w)),
+            UintTy(w) => (false, width_bits(w)),
+            _ => unreachable!(),
+        };
+        let id = expr.id.to_uint();
+        let overflow = self.visit_trap("arithmetic overflow", expr);
+
+        // The compiler builtins check that the result fits in the type of
+        // the variable it's stored in.
+        let builtin = |name: &str| {
+            format!("(\\{ {} __ov{}; if (__builtin_{}_overflow(({}), ({}), &__ov{})) {}; __ov{}; \\})",
+                    ty, id, name, lhs, rhs, id, overflow, id)
+        };
+
+        match *op {
+            PlusOp => Some(builtin("add")),
+            MinusOp => Some(builtin("sub")),
+            TimesOp => Some(builtin("mul")),
+            DivideOp | ModOp => {
+                let zero = self.visit_trap("division by zero", expr);
+                // The one quotient of signed ints that doesn't fit.
+                let min = if signed {
+                    format!(" if (__l{} == INT{}_MIN && __r{} == -1) {};", id, bits, id, overflow)
+                } else {
+                    String::new()
+                };
+                Some(format!("(\\{ {} __l{} = ({}), __r{} = ({}); if (__r{} == 0) {};{} ({})(__l{} {} __r{}); \\})",
+                             ty, id, lhs, id, rhs, id, zero, min, ty, id, op, id))
+            }
+            LeftShiftOp | RightShiftOp => {
+                let shift = self.visit_trap("shift by at least the width of the type", expr);
+                let l = match *op {
+                    LeftShiftOp => format!("(uint32_t)__l{}", id),
+                    _ => format!("__l{}", id),
+                };
+                Some(format!("(\\{ {} __l{} = ({}); uint32_t __r{} = ({}); if (__r{} >= {}) {}; ({})({} {} __r{}); \\})",
+                             ty, id, lhs, id, rhs, id, bits, shift, ty, l, op, id))
+            }
+            _ => None,
+        }
+    }
+
+    // A signed 32 bit division or remainder, without --overflow-checks. The
+    // one quotient that doesn't fit, INT32_MIN / -1, is undefined in C, so
+    // it's worked out as a negation, which wraps to INT32_MIN, and the
+    // remainder is 0, as the constant folder has them. Narrower ints are
+    // divided as ints, where it fits. None for anything else.
+    fn visit_wrapping_div(&self, op: &BinOpNode, t: &Ty, lhs: &str, rhs: &str,
+                          expr: &Expr) -> Option<String> {
+        match *t {
+            GenericIntTy | IntTy(AnyWidth) | IntTy(Width32) => {}
+            _ => return None,
+        }
+        let id = expr.id.to_uint();
+        let min = match *op {
+            DivideOp => format!("(int32_t)(0u - (uint32_t)__l{})", id),
+            ModOp => String::from_str("0"),
+            _ => return None,
+        };
+        Some(format!("(\\{ int32_t __l{} = ({}), __r{} = ({}); __r{} == -1 ? {} : __l{} {} __r{}; \\})",
+                     id, lhs, id, rhs, id, min, id, op, id))
+    }
+
+    // Is this type represented by a struct in C?
+    fn is_aggregate(&self, t: &Ty) -> bool {
+        match self.resolve_ty(t) {
+            Some(StructTy(..)) | Some(EnumTy(..)) | Some(TupleTy(..)) => true,
+            _ => false,
+        }
+    }
+
+    fn visit_ty(&self, t: &Ty) -> String {
+        match *t {
+            BoolTy => String::from_str("bool"),
+            UnitTy => String::from_str("void"),
+            StrTy => String::from_str("__str"),
+            GenericIntTy => String::from_str("int32_t"),
+            IntTy(w) => format!("int{}_t", width_bits(w)),
+            UintTy(w) => format!("uint{}_t", width_bits(w)),
+            // The qualifier goes after the pointee, so that it applies to
+            // the right level of a pointer to a pointer.
+            PtrTy(ref t, true) => format!("{} volatile*", self.visit_ty(*t)),
+            PtrTy(ref t, false) | ArrayTy(ref t, _) => {
+                format!("{}*", self.visit_ty(*t))
+            },
+            BoundTy(..) => self.visit_ty(&self.resolve_ty_or_fail(t)),
+            FuncTy(ref d, ref r) => {
+                let ty = self.visit_ty(*r);
+                let args = self.visit_list(d, |x| self.visit_ty(x), ", ");
+                format!("{}(*)({})", ty, args)
+            }
+            // Each distinct tuple type, and each instance of a generic
+            // struct or enum, gets its own struct; see visit_type_defs.
+            TupleTy(..) | EnumTy(..) => self.aggregate_name(t),
+            StructTy(..) => format!("{} {}", self.aggregate_keyword(t), self.aggregate_name(t)),
+            _ => fail!("Not supported yet: {}", t),
+        }
+    }
+
+    // If the path names an enum variant, the enum and the variant's tag.
+    fn find_variant(&self, path: &Path) -> Option<(NodeId, i64)> {
+        let did = self.session.resolver.def_from_path(path);
+        self.variants.find(&did).map(|v| *v)
+    }
+
+    // The name of a variant's member in its enum's union.
+    fn visit_path_in_enum_access(&self, path: &Path) -> String {
+        self.visit_name(&path.val.elems.last().unwrap().val.name)
+    }
+
+    // Notes that the item `did` is used, which an extern has to be for it to
+    // be declared.
+    fn note_use(&self, did: NodeId) {
+        match self.session.defmap.find(&did) {
+            Some(&ExternFuncDef(..)) => { self.used_externs.borrow_mut().insert(did); }
+            _ => {}
+        }
+    }
+
+    fn visit_path(&self, path: &Path) -> String {
+        let did = self.session.resolver.def_from_path(path);
+        match self.session.defmap.find(&did) {
+            Some(&FuncDef(_, _, ref tps)) if !tps.is_empty() => {
+                return self.fn_instance_name(did, path);
+            }
+            _ => {}
+        }
+        self.note_use(did);
+
+        match self.names.find(&did) {
+            Some(name) => name.clone(),
+            // Not an item, so it must be a local.
+            None => self.visit_name(&path.val.elems.last().unwrap().val.name),
+        }
+    }
+
+    // The C name of a local variable or a field.
+    fn visit_name(&self, name: &Name) -> String {
+        escape_ident(self.session.interner.name_to_str(name))
+    }
+
+    fn visit_ident(&self, ident: &Ident) -> String {
+        self.visit_name(&ident.val.name)
+    }
+
+    // The C name of an item; see ItemCollector.
+    fn item_name<'a>(&'a self, ident: &Ident) -> &'a str {
+        self.names.find(&ident.id).unwrap().as_slice()
+    }
+
+    fn visit_lit(&self, lit: &Lit) -> String {
+        match lit.val {
+            NumLit(ref n, _) => format!("{}", n),
+            StringLit(ref s) => {
+                format!("((__str)\\{ (const unsigned char *){}, {} \\})",
+                        c_string(s.as_slice()), s.len())
+            }
+            BoolLit(ref b) => String::from_str(if *b { "true" } else { "false" }),
+            NullLit => String::from_str("NULL"),
+        }
+    }
+
+    fn visit_expr(&self, expr: &Expr) -> String {
+        match expr.val {
+            UnitExpr => String::from_str("({})"),
+            LitExpr(ref l) => self.visit_lit(l),
+            TupleExpr(ref es) => {
+                let ty = self.visit_ty(self.typemap.types.get(&expr.id.to_uint()));
+                let es = self.visit_list(es, |e| self.visit_expr(e), ", ");
+                format!("(({})\\{ {} \\})", ty, es)
+            }
+            // The typechecker only lets these initialize variables, which
+            // visit_init takes care of.
+            ArrayExpr(..) => unreachable!(),
+            GroupExpr(ref e) => format!("({})", self.visit_expr(*e)),
+            PathExpr(ref p) => match self.find_variant(p) {
+                Some(_) => {
+                    let t = self.typemap.types.get(&expr.id.to_uint());
+                    let did = self.session.resolver.def_from_path(p);
+                    format!("(({})\\{ {} \\})", self.visit_ty(t),
+                            self.visit_variant_init(t, did, vec!()))
+                }
+                None => self.visit_path(p),
+            },
+            StructExpr(_, ref flds, None) => {
+                let ty = self.visit_ty(self.typemap.types.get(&expr.id.to_uint()));
+                let flds = self.visit_list(flds, |&(ref name, ref e)| {
+                    let name = self.visit_name(name);
+                    format!(".{} = {}", name, self.visit_expr(e))
+                }, ", ");
+                format!("(({})\\{ {} \\})", ty, flds)
+            }
+            StructExpr(_, ref flds, Some(ref base)) => {
+                // Copy the base into a temporary, then overwrite the fields
+                // that were given explicitly.
+                let ty = self.visit_ty(self.typemap.types.get(&expr.id.to_uint()));
+                let tmp = format!("__struct{}", expr.id.to_uint());
+                let base = self.visit_expr(*base);
+                let flds = self.visit_list(flds, |&(ref name, ref e)| {
+                    let name = self.visit_name(name);
+                    format!("{}.{} = ({});", tmp, name, self.visit_expr(e))
+                }, " ");
+                format!("(\\{ {} {} = ({}); {} {}; \\})", ty, tmp, base, flds, tmp)
+            }
+            BinOpExpr(ref op, ref lhs, ref rhs) => {
+                let lhs = self.visit_expr(*lhs);
+                let op_str = self.visit_binop(op);
+                let rhs = self.visit_expr(*rhs);
+                match self.int_ty(expr) {
+                    Some(ref t) if self.overflow == Checked => {
+                        match self.visit_checked_binop(&op.val, t, lhs.as_slice(),
+                                                       rhs.as_slice(), expr) {
+                            Some(e) => e,
+                            None => format!("(({})(({}) {} ({})))",
+                                            self.visit_ty(t), lhs, op_str, rhs),
+                        }
+                    }
+                    // C promotes anything narrower than int to int before
+                    // doing arithmetic on it, and signed overflow is
+                    // undefined, so the operations that can overflow are
+                    // done in uint32_t. Every result is truncated back to
+                    // the type it should have.
+                    Some(ref t) => {
+                        let ty = self.visit_ty(t);
+                        match op.val {
+                            PlusOp | MinusOp | TimesOp | LeftShiftOp => {
+                                format!("(({})((uint32_t)({}) {} (uint32_t)({})))",
+                                        ty, lhs, op_str, rhs)
+                            }
+                            _ => match self.visit_wrapping_div(&op.val, t, lhs.as_slice(),
+                                                               rhs.as_slice(), expr) {
+                                Some(e) => e,
+                                None => format!("(({})(({}) {} ({})))", ty, lhs, op_str, rhs),
+                            },
+                        }
+                    }
+                    None => format!("({}) {} ({})", lhs, op_str, rhs),
+                }
+            }
+            UnOpExpr(ref op, ref e) => {
+                let op_str = self.visit_unop(op);
+                let e = match op.val {
+                    Deref => self.visit_nonnull(*e),
+                    _ => self.visit_expr(*e),
+                };
+                match (op.val, self.int_ty(expr)) {
+                    (Negate, Some(ref t)) if self.overflow == Checked => {
+                        self.visit_checked_binop(&MinusOp, t, "0", e.as_slice(), expr).unwrap()
+                    }
+                    (Negate, Some(ref t)) => format!("(({})-(uint32_t)({}))", self.visit_ty(t), e),
+                    (BitNot, Some(ref t)) => format!("(({})~({}))", self.visit_ty(t), e),
+                    _ => format!("{}({})", op_str, e),
+                }
+            }
+            IndexExpr(ref exp, ref idx) => {
+                let len = match self.resolve_ty(self.typemap.types.get(&exp.id.to_uint())) {
+                    Some(ArrayTy(_, len)) => len,
+                    _ => None,
+                };
+                let exp = self.visit_expr(*exp);
+                let idx = self.visit_expr(*idx);
+                match len {
+                    Some(len) if self.debug_checks => {
+                        format!("({})[__mc_check_index(({}), {}, {})]",
+                                exp, idx, len, self.visit_pos(&expr.id))
+                    }
+                    _ => format!("({})[{}]", exp, idx),
+                }
+            }
+            DotExpr(ref exp, ref field) => {
+                let exp = self.visit_expr(*exp);
+                let field = self.visit_name(field);
+                format!("({}).{}", exp, field)
+            }
+            TupleIndexExpr(ref exp, idx) => {
+                let exp = self.visit_expr(*exp);
+                format!("({}).field{}", exp, idx)
+            }
+            ArrowExpr(ref exp, ref field) => {
+                let exp = self.visit_nonnull(*exp);
+                let field = self.visit_name(field);
+                format!("({})->{}", exp, field)
+            }
+            AssignExpr(ref op, ref lhs, ref rhs) => {
+                let lhs_ty = self.int_ty(*lhs);
+                let lhs = self.visit_expr(*lhs);
+                let rhs = self.visit_expr(*rhs);
+
+                // With overflow checks, or for a signed division, compute
+                // the result through a pointer to the left hand side, so
+                // that it's only evaluated once.
+                let checked = match (op, &lhs_ty) {
+                    (&Some(ref op), &Some(ref t)) => {
+                        let id = expr.id.to_uint();
+                        let ptr = format!("(*__p{})", id);
+                        let e = if self.overflow == Checked {
+                            self.visit_checked_binop(&op.val, t, ptr.as_slice(),
+                                                     rhs.as_slice(), expr)
+                        } else {
+                            self.visit_wrapping_div(&op.val, t, ptr.as_slice(),
+                                                    rhs.as_slice(), expr)
+                        };
+                        // __typeof__ keeps the left hand side volatile, if it
+                        // is.
+                        e.map(|e| format!("(\\{ __typeof__({}) *__p{} = &({}); {} = {}; \\})",
+                                          lhs, id, lhs, ptr, e))
+                    }
+                    _ => None,
+                };
+
+                // As with BinOpExpr, converting the right hand side makes
+                // the arithmetic happen in uint32_t, and the assignment
+                // truncates the result.
+                let wrap = match *op {
+                    Some(WithId { val: PlusOp, .. }) |
+                    Some(WithId { val: MinusOp, .. }) |
+                    Some(WithId { val: TimesOp, .. }) => lhs_ty.is_some(),
+                    _ => false,
+                };
+                let op = op.map_or(String::new(), |op| format!("{}", op));
+                match checked {
+                    Some(e) => e,
+                    None if wrap => format!("({}) {}= (uint32_t)({})", lhs, op, rhs),
+                    None => format!("({}) {}= ({})", lhs, op, rhs),
+                }
+            }
+            CallExpr(ref f, ref args) => {
+                let res_ty = self.typemap.types.get(&expr.id.to_uint());
+                let res_type = self.visit_ty(res_ty);
+                match f.val {
+                    PathExpr(ref path) => {
+                        match self.find_variant(path) {
+                            Some(_) => {
+                                let did = self.session.resolver.def_from_path(path);
+                                let args = args.iter().map(|arg| self.visit_expr(arg)).collect();
+                                let value = format!("(({})\\{ {} \\})", res_type,
+                                                    self.visit_variant_init(res_ty, did, args));
+                                match self.enum_layout(res_ty) {
+                                    // The field mustn't hold a value that
+                                    // stands for another variant.
+                                    NicheLayout(_, _, ref niche) if self.debug_checks => {
+                                        let id = expr.id.to_uint();
+                                        let used = self.enum_discs(res_ty).len() as u64 - 1;
+                                        let slot = format!("__variant{}{}", id, niche.path);
+                                        let reserved = if used == 1 {
+                                            format!("{} == {}", slot, niche.start)
+                                        } else {
+                                            format!("{} >= {} && {} < {}", slot, niche.start,
+                                                    slot, niche.start + used)
+                                        };
+                                        format!("(\\{ {} __variant{} = {}; if ({}) {}; __variant{}; \\})",
+                                                res_type, id, value, reserved,
+                                                self.visit_trap("enum field holds a value reserved for another variant", expr),
+                                                id)
+                                    }
+                                    _ => value,
+                                }
+                            }
+                            None => {
+                                let name = self.visit_path(path);
+                                // The integers past the fixed arguments of
+                                // a variadic extern are cast to the types
+                                // the typechecker gave them, so that C
+                                // passes them at that width.
+                                let did = self.session.resolver.def_from_path(path);
+                                let fixed = match self.session.defmap.find(&did) {
+                                    Some(&ExternFuncDef(ref fixed, _, _, true)) => fixed.len(),
+                                    _ => args.len(),
+                                };
+                                let mut n = 0;
+                                let args = self.visit_list(args, |x| {
+                                    n += 1;
+                                    match self.int_ty(x) {
+                                        Some(ref t) if n > fixed =>
+                                            format!("(({}){})", self.visit_ty(t), self.visit_expr(x)),
+                                        _ => self.visit_expr(x),
+                                    }
+                                }, ", ");
+                                if self.is_aggregate(res_ty) {
+                                    // C won't cast to a struct type.
+                                    format!("{}({})", name, args)
+                                } else {
+                                    format!("(({}){}({}))", res_type, name, args)
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        let f = self.visit_expr(*f);
+                        let args = self.visit_list(args, |x| self.visit_expr(x), ", ");
+                        format!("{}({})", f, args)
+                    }
+                }
+            }
+            SizeofExpr(ref t) => {
+                // A declaration with no name is how C writes array types.
+                format!("((uint32_t)sizeof({}))", self.visit_name_and_type("", t))
+            }
+            OffsetofExpr(ref t, ref fld) => {
+                format!("((uint32_t)offsetof({}, {}))", self.visit_type(t), self.visit_name(fld))
+            }
+            // Allocations go through the run time, which gets the memory
+            // from the allocator hook and zeroes it.
+            NewExpr(..) | NewTypeExpr(..) | NewArrayExpr(..) => {
+                let t = match self.resolve_ty_or_fail(self.typemap.types.get(&expr.id.to_uint())) {
+                    PtrTy(t, _) => self.visit_ty(&*t),
+                    _ => unreachable!(),
+                };
+                let pos = self.visit_pos(&expr.id);
+                match expr.val {
+                    NewArrayExpr(_, ref n) => {
+                        format!("((__typeof__({})*)__mc_new_array(sizeof({}), ({}), _Alignof({}), {}))",
+                                t, t, self.visit_expr(*n), t, pos)
+                    }
+                    NewExpr(ref e) if !self.names_new_type(*e) => {
+                        let id = expr.id.to_uint();
+                        format!("(\\{ __typeof__({}) *__new{} = __mc_new(sizeof({}), _Alignof({}), {}); *__new{} = {}; __new{}; \\})",
+                                t, id, t, t, pos, id, self.visit_expr(*e), id)
+                    }
+                    _ => {
+                        format!("((__typeof__({})*)__mc_new(sizeof({}), _Alignof({}), {}))",
+                                t, t, t, pos)
+                    }
+                }
+            }
+            FreeExpr(ref e) => format!("mc_free((uint8_t*)({}))", self.visit_expr(*e)),
+            CastExpr(ref e, ref t) => {
+                let ty = self.visit_type(t);
+                let e_ty = self.typemap.types.get(&e.id.to_uint());
+                let res_ty = self.typemap.types.get(&expr.id.to_uint());
+                let id = expr.id.to_uint();
+                let expr = self.visit_expr(*e);
+                match (self.resolve_ty(e_ty), self.resolve_ty(res_ty)) {
+                    (Some(StrTy), _) => format!("({})(({}).ptr)", ty, expr),
+                    (Some(EnumTy(..)), _) => format!("({})(({}).tag)", ty, expr),
+                    (_, Some(EnumTy(nid, _))) => {
+                        // Only a value one of the variants has makes an enum.
+                        let discs = match self.find_item(nid).val {
+                            EnumItem(_, ref variants, _) => discriminants(variants),
+                            _ => unreachable!(),
+                        };
+                        let trap = self.visit_trap("invalid enum discriminant", *e);
+                        // An enum without variants has no values at all.
+                        let check = if discs.is_empty() {
+                            format!("{};", trap)
+                        } else {
+                            let valid = self.visit_list(&discs, |disc| {
+                                format!("(int64_t)__enum{} != {}", id, disc)
+                            }, " && ");
+                            format!("if ({}) {};", valid, trap)
+                        };
+                        format!("(\\{ __typeof__({}) __enum{} = ({}); {} (({})\\{ .tag = __enum{} \\}); \\})",
+                                expr, id, expr, check, ty, id)
+                    }
+                    _ => format!("({})({})", ty, expr),
+                }
+            }
+            IfExpr(ref e, ref b1, ref b2) => {
+                let cond = self.visit_expr(*e);
+                let branch_diverges = |b: &Block| {
+                    b.expr.as_ref().map_or(false, |e| self.diverges(e))
+                };
+
+                if branch_diverges(*b1) || branch_diverges(*b2) {
+                    // A diverging branch is void in C, and can't be mixed
+                    // with a value in a conditional expression, so emit an
+                    // if statement that stores the value in a temporary.
+                    let tmp = match self.resolve_ty(self.typemap.types.get(&expr.id.to_uint())) {
+                        Some(UnitTy) | Some(BottomTy) | None => None,
+                        Some(t) => Some((self.visit_ty(&t), format!("__if{}", expr.id.to_uint()))),
+                    };
+                    let name = tmp.as_ref().map(|&(_, ref name)| name.clone());
+                    let thenpart = self.visit_branch(*b1, &name);
+                    let elsepart = self.visit_branch(*b2, &name);
+                    match tmp {
+                        Some((ty, name)) => {
+                            format!("(\\{ {} {}; if ({}) {} else {} {}; \\})",
+                                    ty, name, cond, thenpart, elsepart, name)
+                        }
+                        None => format!("(\\{ if ({}) {} else {} \\})", cond, thenpart, elsepart),
+                    }
+                } else {
+                    let thenpart = self.visit_block_expr(*b1);
+                    let elsepart = self.visit_block_expr(*b2);
+                    format!("(({})?({}):({}))", cond, thenpart, elsepart)
+                }
+            }
+            BlockExpr(ref b) | UnsafeExpr(ref b) => self.visit_block_expr(*b),
+            ReturnExpr(ref e) => {
+                let expr = self.visit_expr(*e);
+                if self.in_entry.get() {
+                    format!("\\{ {}; return 0; \\}", expr)
+                } else {
+                    format!("return/*expr*/ {};", expr)
+                }
+            }
+            BreakExpr => format!("break;"),
+            ContinueExpr => format!("continue;"),
+            // The check goes, but the condition is still evaluated for its
+            // side effects.
+            AssertExpr(ref e, _, _) if self.release => format!("((void)({}))", self.visit_expr(*e)),
+            AssertExpr(ref e, ref msg, ref text) => {
+                let cond = self.visit_expr(*e);
+                let what = format!("assertion failed: {}", text);
+                let msg = match *msg {
+                    Some(ref msg) => self.visit_expr(*msg),
+                    None => String::from_str("((__str)\\{ 0, 0 \\})"),
+                };
+                format!("(({}) ? (void)0 : __mc_panic({}, {}, {}))",
+                        cond, self.visit_pos(&expr.id), c_string(what.as_slice()), msg)
+            }
+            PanicExpr(ref msg) => {
+                let msg = self.visit_expr(*msg);
+                format!("__mc_panic({}, \"panicked\", {})", self.visit_pos(&expr.id), msg)
+            }
+            // Still a trap, just without the message and location.
+            UnreachableExpr if self.release => String::from_str("__builtin_trap()"),
+            UnreachableExpr => {
+                format!("__mc_panic({}, \"entered unreachable code\", ((__str)\\{ 0, 0 \\}))",
+                        self.visit_pos(&expr.id))
+            }
+            AsmExpr(ref template, ref operands, ref clobbers) => {
+                // GCC wants the outputs before the inputs.
+                let mut outs = vec!();
+                let mut ins = vec!();
+                for (i, op) in operands.iter().enumerate() {
+                    match op.kind {
+                        AsmIn => ins.push((i, op)),
+                        AsmOut | AsmInOut => outs.push((i, op)),
+                    }
+                }
+                let order = outs.iter().chain(ins.iter()).map(|&(i, _)| i).collect();
+
+                let outs = self.visit_list(&outs, |&(_, op)| self.visit_asm_operand(op), ", ");
+                let ins = self.visit_list(&ins, |&(_, op)| self.visit_asm_operand(op), ", ");
+                let clobbers = self.visit_list(clobbers, |c| c_string(c.as_slice()), ", ");
+
+                // asm is a statement in C, so it's wrapped up to be usable
+                // as an expression.
+                format!("(\\{ __asm__ __volatile__({} : {} : {} : {}); \\})",
+                        c_string(c_asm_template(template.as_slice(), &order).as_slice()),
+                        outs, ins, clobbers)
+            }
+            AtomicExpr(FenceOp, _, order) => {
+                format!("atomic_thread_fence({})", c_memory_order(order))
+            }
+            AtomicExpr(op, ref es, order) => {
+                // C only promises atomicity for _Atomic objects, so the
+                // pointer is converted to point to one, keeping it volatile
+                // if it was.
+                let ptr_ty = self.typemap.types.get(&es.get(0).id.to_uint());
+                let (elem, volatile) = match self.resolve_ty_or_fail(ptr_ty) {
+                    PtrTy(t, volatile) => (self.visit_ty(&*t), volatile),
+                    _ => unreachable!(),
+                };
+                let ptr = format!("((_Atomic({}) {}*)({}))", elem,
+                                  if volatile { "volatile " } else { "" },
+                                  self.visit_expr(es.get(0)));
+                let args: Vec<String> = es.iter().skip(1).map(|e| self.visit_expr(e)).collect();
+                let mo = c_memory_order(order);
+
+                match op {
+                    LoadOp => format!("atomic_load_explicit({}, {})", ptr, mo),
+                    StoreOp => format!("atomic_store_explicit({}, {}, {})", ptr, args.get(0), mo),
+                    FetchAddOp => format!("atomic_fetch_add_explicit({}, {}, {})", ptr, args.get(0), mo),
+                    CasOp => {
+                        // Evaluates to the old value. If the exchange fails,
+                        // C stores that in the expected value, and if it
+                        // succeeds they were equal anyway. A failed exchange
+                        // doesn't store, so it can't have release ordering.
+                        let fail_mo = c_memory_order(match order {
+                            Release => Relaxed,
+                            order => order,
+                        });
+                        let id = expr.id.to_uint();
+                        format!("(\\{ __typeof__({}) __casp{} = {}; {} __cas{} = ({}); atomic_compare_exchange_strong_explicit(__casp{}, &__cas{}, ({}), {}, {}); __cas{}; \\})",
+                                ptr, id, ptr, elem, id, args.get(0), id, id, args.get(1), mo, fail_mo, id)
+                    }
+                    FenceOp => unreachable!(),
+                }
+            }
+            WhileExpr(ref e, ref b) => {
+                let cond = self.visit_expr(*e);
+                let body = self.visit_block_expr(*b);
+                format!("while({}) \\{\n{};\\}\n", cond, body)
+            }
+            ForExpr(ref e1, ref e2, ref e3, ref b) => {
+                let e1 = self.visit_expr(*e1);
+                let e2 = self.visit_expr(*e2);
+                let e3 = self.visit_expr(*e3);
+                let body = self.visit_block_expr(*b);
+                format!("for({};{};{}) \\{\n{};\\}\n", e1, e2, e3, body)
+            }
+            MatchExpr(ref e, ref arms) => {
+                // TODO: allow types other than ints.
+                let e_ty = self.typemap.types.get(&e.id.to_uint());
+                let id = expr.id.to_uint();
+                let expr = self.visit_expr(*e);
+                let tag = self.visit_tag(expr.as_slice(), e_ty, id);
+                let arms = self.visit_list(arms, |arm| {
+                    let (path, vars) = match arm.pat.val {
+                        VariantPat(ref path, ref args) => (path, args),
+                        _ => fail!("Only VariantPats are supported in match arms for now")
+                    };
+
+                    let (_, idx) = self.find_variant(path).unwrap();
+                    let name = self.visit_path_in_enum_access(path);
+
+                    // A discarded field is skipped, but still counts when
+                    // numbering the ones after it.
+                    let mut n = 0;
+                    let vars = self.visit_list(vars, |var| {
+                        n += 1;
+                        match var.val {
+                            IdentPat(ref id, _) => {
+                                let ty = self.visit_ty(self.typemap.types.get(&var.id.to_uint()));
+                                format!("{} {} = ({}.val.{}.field{});",
+                                        ty, self.visit_ident(id), expr.as_slice(), name, n - 1)
+                            }
+                            DiscardPat(..) => String::new(),
+                            _ => fail!("Only IdentPats and _ are supported in the arguments of a VariantPat in a match arm for now"),
+                        }
+                    }, " ");
+
+                    let body = self.visit_expr(&arm.body);
+                    if self.diverges(&arm.body) {
+                        format!("case {}: \\{\n {} ({}); break;\\}\n", idx, vars, body)
+                    } else {
+                        format!("case {}: \\{\n {} _ = ({}); break;\\}\n", idx, vars, body)
+                    }
+                }, "\n");
+
+                // Either a variant that no arm is for, which would leave
+                // the match without a value, or memory that was never a
+                // value of this enum at all.
+                let default = if self.debug_checks {
+                    format!("\ndefault: {};", self.visit_trap("enum tag with no match arm", *e))
+                } else {
+                    String::new()
+                };
+
+                format!("(\\{ int _; switch({}) \\{\n{}{} \n\\} _; \\})", tag, arms, default)
+            }
+        }
+    }
+
+    fn visit_module(&self) -> String {
+        // Items can refer to functions and statics that come after them (or
+        // a static to itself), so declare all of those up front.
+        let mut protos = vec!();
+        let mut externs = vec!();
+        let mut items = vec!();
+        let consts = ConstEvaluator::new(&self.session, &self.typemap, self.overflow, &self.items);
+        for item in self.items.iter() {
+            match item.val {
+                FuncItem(ref name, ref args, ref t, _, ref tps) if tps.is_empty() => {
+                    protos.push(format!("{}{};", self.linkage(name),
+                                        self.visit_func_sig(self.item_name(name), args, t)));
+                }
+                // A generic extern can't be declared in C, so it had better
+                // be a macro.
+                ExternFuncItem(ref name, ref args, ref t, ref tps, variadic, _)
+                    if tps.is_empty() => {
+                    let c_name = self.item_name(name);
+                    let proto = match LIBC_PROTOS.iter().find(|&&(n, _)| n == c_name) {
+                        Some(&(_, proto)) => proto.to_string(),
+                        None => {
+                            let mut sig = self.visit_func_sig(c_name, args, t);
+                            if variadic {
+                                sig.pop_char();
+                                sig.push_str(", ...)");
+                            }
+                            format!("{};", sig)
+                        }
+                    };
+                    externs.push((name.id, proto));
+                }
+                StaticItem(ref id, ref ty, _) => {
+                    protos.push(format!("static {};", self.visit_static_decl(id, ty)));
+                }
+                _ => {}
+            }
+            items.push(self.visit_item(item, &consts));
+        }
+
+        // Emitting an instance of a generic function can turn up more
+        // instances to emit.
+        loop {
+            let next = self.fn_queue.borrow_mut().shift();
+            let (did, tys, name) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            match self.find_item(did).val {
+                FuncItem(_, ref args, ref t, ref block, ref tps) => {
+                    self.with_instance(tps, &tys, || {
+                        protos.push(format!("static {};", self.visit_func_sig(name.as_slice(), args, t)));
+                        items.push(format!("static {}", self.visit_func(name.as_slice(), args, t, block)));
+                    });
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // Only now is it known which externs are used. The rest, like most
+        // of the prelude's, aren't declared, as their types needn't match
+        // what the C library has.
+        for (id, proto) in externs.move_iter() {
+            if self.used_externs.borrow().contains(&id) {
+                protos.push(proto);
+            }
+        }
+
+        // This has to come last, once every type that's used is known.
+        let type_defs = self.visit_type_defs();
+        format!("{}\n{}\n{}", type_defs, protos.connect("\n"), items.connect("\n\n"))
+    }
+
+    // A header for C code that calls the exported functions, with their
+    // prototypes and every type that they mention.
+    fn visit_header(&self, guard: &str) -> String {
+        let mut protos = vec!();
+        for item in self.items.iter() {
+            match item.val {
+                FuncItem(ref name, ref args, ref t, _, _)
+                    if self.session.parser.is_exported(&name.id) => {
+                    protos.push(format!("{};", self.visit_func_sig(self.item_name(name), args, t)));
+                }
+                _ => {}
+            }
+        }
+
+        let type_defs = self.visit_used_type_defs();
+        format!("#ifndef {0}\n#define {0}\n\n{1}\n{2}\n\n{3}\n{4}\n\n#endif /* {0} */",
+                guard, HEADER_INCLUDES.connect("\n"), STR_TYPEDEF, type_defs, protos.connect("\n"))
+    }
+}
+
+// What a C file needs in order to use the types of a program.
+static HEADER_INCLUDES: &'static [&'static str] = &[
+    "#include <stddef.h>",
+    "#include <stdint.h>",
+    "#include <stdbool.h>",
+];
+// How the C library declares the functions of its that a program is likely
+// to declare too. These take the place of the prototypes that would be made
+// from the program's declarations, which can't say `size_t` or `const`, and
+// so would clash with <stdio.h>, or be complained about by C compilers that
+// know the functions as builtins.
+// The arguments are converted as C converts them, so a `u32` size is
+// widened to a `size_t` as it should be.
+static LIBC_PROTOS: &'static [(&'static str, &'static str)] = &[
+    ("printf", "int printf(const char *fmt, ...);"),
+    ("puts", "int puts(const char *s);"),
+    ("putchar", "int putchar(int c);"),
+    ("memcpy", "void *memcpy(void *dst, const void *src, size_t n);"),
+    ("memmove", "void *memmove(void *dst, const void *src, size_t n);"),
+    ("memset", "void *memset(void *dst, int c, size_t n);"),
+    ("memcmp", "int memcmp(const void *a, const void *b, size_t n);"),
+];
+
+// Every header has its own copy of this, so it's guarded separately, for
+// the C files that include more than one.
+static STR_TYPEDEF: &'static str =
+    "#ifndef MC_STR_DEFINED\n#define MC_STR_DEFINED\n\
+     typedef struct __str { const unsigned char *ptr; uint32_t len; } __str;\n\
+     #endif";
+
+pub struct CTarget {
+    overflow: OverflowMode,
+    debug_checks: bool,
+    release: bool,
+    // If set, emit a header for the exported functions, with this include
+    // guard, rather than the program itself. The guard is made from the name
+    // of the program's file, if it has one.
+    header: Option<String>,
+    // Whether to emit code that doesn't depend on the C library, to be
+    // built with -ffreestanding -nostdlib.
+    freestanding: bool,
+}
+
+impl Target for CTarget {
+    fn new(args: Vec<String>) -> CTarget {
+        let overflow = if args.iter().any(|a| a.as_slice() == "--overflow-checks") {
+            Checked
+        } else {
+            Wrapping
+        };
+
+        let header = if args.iter().any(|a| a.as_slice() == "--emit=header") {
+            let guard = args.iter()
+                .find(|a| a.as_slice().starts_with("--header-guard="))
+                .map(|a| a.as_slice().slice_from("--header-guard=".len()).to_string());
+            Some(guard.unwrap_or(String::from_str("MC_EXPORTS_H")))
+        } else {
+            None
+        };
+
+        CTarget {
+            overflow: overflow,
+            debug_checks: args.iter().any(|a| a.as_slice() == "--debug-checks"),
+            release: args.iter().any(|a| a.as_slice() == "--release"),
+            header: header,
+            freestanding: args.iter().any(|a| a.as_slice() == "--freestanding"),
+        }
+    }
+
+    fn compile(&self, p: Package) {
+        let mut stderr = stdio::stderr();
+
+        let Package {
+            module:  module,
+            session: mut session,
+            typemap: typemap,
+        } = p;
+
+        let (names, variants, items, used) = ItemCollector::collect(&session, &module,
+                                                                    self.freestanding);
+
+        let cc = CCrossCompiler {
+            names: names,
+            variants: variants,
+            items: items,
+            used: RefCell::new(used),
+            instances: RefCell::new(TreeMap::new()),
+            fn_queue: RefCell::new(vec!()),
+            in_entry: Cell::new(false),
+            used_externs: RefCell::new(TreeSet::new()),
+            aggregates: RefCell::new(TreeMap::new()),
+            generics: RefCell::new(TreeMap::new()),
+            subst: RefCell::new(TreeMap::new()),
+            overflow: self.overflow,
+            debug_checks: self.debug_checks,
+            release: self.release,
+            session: session,
+            typemap: typemap,
+        };
+
+/*
+        match writeln!(stderr, "{}", module) {
+            Err(e) => fail!("{}", e),
+            _ => {}
+        }
+*/
+        /* what?
+        match writeln!(stderr, "{}", cc.variants) {
+            Err(e) => fail!("{}", e),
+            _ => {}
+        }
+        */
+
+        match self.header {
+            Some(ref guard) => {
+                println!("{}", cc.visit_header(guard.as_slice()));
+                return;
+            }
+            None => {}
+        }
+
+        // The program's own declarations of the C library's functions are
+        // replaced with the library's (see LIBC_PROTOS), so that they don't
+        // clash with the ones in <stdio.h>.
+        for include in HEADER_INCLUDES.iter() {
+            println!("{}", include);
+        }
+        println!("{}", "#include <stdatomic.h>");
+        if !self.freestanding {
+            // For stderr, which the run time reports to.
+            println!("{}", "#include <stdio.h>");
+        }
+        println!("{}", STR_TYPEDEF);
+        println!("{}", "static uint32_t __mc_str_len(__str s) { return s.len; }");
+        if self.freestanding {
+            // There's nowhere to report to, so traps and panics just stop.
+            println!("{}", "static void __mc_trap(const char *msg, const char *pos) { (void)msg; (void)pos; __builtin_trap(); }");
+            println!("{}", "static void __mc_panic(const char *pos, const char *what, __str msg) { (void)pos; (void)what; (void)msg; __builtin_trap(); }");
+        } else {
+            println!("{}", "static void __mc_trap(const char *msg, const char *pos) { __builtin_fprintf(stderr, \"%s: %s\\n\", pos, msg); __builtin_abort(); }");
+            println!("{}", "static void __mc_panic(const char *pos, const char *what, __str msg) { __builtin_fprintf(stderr, \"%s: %s\", pos, what); if (msg.ptr) __builtin_fprintf(stderr, \": %.*s\", (int)msg.len, msg.ptr); __builtin_fprintf(stderr, \"\\n\"); __builtin_abort(); }");
+        }
+        println!("{}", "static inline uint32_t __mc_check_index(uint32_t i, uint32_t len, const char *pos) { if (i >= len) __mc_trap(\"index out of bounds\", pos); return i; }");
+        println!("{}", "#define __mc_check_null(p, pos) ({ __typeof__(p) __mc_p = (p); if (!__mc_p) __mc_trap(\"null pointer dereference\", pos); __mc_p; })");
+        // The allocator hook, which a program can define for itself as
+        // `pub extern fn mc_alloc(size: u32, align: u32) -> *u8` and
+        // `pub extern fn mc_free(p: *u8)`. Otherwise the C library's
+        // allocator is used, through weak definitions so that the hook can
+        // still come from another object file. Without the C library
+        // there's nothing to fall back on, and the hook has to be linked in.
+        let own_alloc = cc.defines_export("mc_alloc");
+        if own_alloc != cc.defines_export("mc_free") {
+            fail!("A program that defines mc_alloc or mc_free has to define both");
+        }
+        println!("{}", "uint8_t *mc_alloc(uint32_t size, uint32_t align);");
+        println!("{}", "void mc_free(uint8_t *p);");
+        if !own_alloc && !self.freestanding {
+            println!("{}", "__attribute__((weak)) uint8_t *mc_alloc(uint32_t size, uint32_t align) { uint32_t n = (size + align - 1) / align * align; return __builtin_aligned_alloc(align, n ? n : align); }");
+            println!("{}", "__attribute__((weak)) void mc_free(uint8_t *p) { __builtin_free(p); }");
+        }
+        println!("{}", "static inline void *__mc_new(uint32_t size, uint32_t align, const char *pos) { uint8_t *p = mc_alloc(size, align); if (!p) __mc_trap(\"out of memory\", pos); for (uint32_t i = 0; i < size; i++) p[i] = 0; return p; }");
+        println!("{}", "static inline void *__mc_new_array(uint32_t size, uint32_t n, uint32_t align, const char *pos) { uint32_t total; if (__builtin_mul_overflow(size, n, &total)) __mc_trap(\"allocation too large\", pos); return __mc_new(total, align, pos); }");
+        // The arithmetic intrinsics, which programs declare as generic
+        // externs. The wrapping ones compute in uint32_t
+        // for the same reason that BinOpExprs do, and the checked ones store
+        // the result through their third argument and return whether it
+        // fit.
+        for op in [("add", "+"), ("sub", "-"), ("mul", "*")].iter() {
+            let (name, sym) = *op;
+            println!("#define wrapping_{}(a, b) ((__typeof__(a))((uint32_t)(a) {} (uint32_t)(b)))",
+                     name, sym);
+            println!("#define checked_{}(a, b, r) (!__builtin_{}_overflow((a), (b), (r)))",
+                     name, name);
+        }
+        println!("{}", cc.visit_module());
+    }
+}
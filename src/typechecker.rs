@@ -1,4 +1,4 @@
-use std::collections::{SmallIntMap, TreeMap, EnumSet};
+use std::collections::{SmallIntMap, TreeMap, TreeSet, EnumSet};
 use std::collections::enum_set::CLike;
 use resolver::Resolver;
 use session::Session;
@@ -6,13 +6,14 @@ use util::{IntKind, GenericInt, SignedInt, UnsignedInt};
 use util::{Width, AnyWidth, Width8, Width16, Width32};
 
 use std::fmt;
+use std::mem;
 
 use ast::*;
 use ast::defmap::*;
 use ast::visit::*;
 
 #[deriving(Eq, PartialEq, Show, Clone)]
-struct BoundsId(uint);
+pub struct BoundsId(uint);
 
 impl BoundsId {
     pub fn to_uint(&self) -> uint {
@@ -29,7 +30,9 @@ pub enum Ty {
     UintTy(Width),
     StrTy,
     UnitTy,
-    PtrTy(Box<Ty>),
+    // The pointee, and whether it is volatile. Every load and store
+    // through a volatile pointer has to happen, in order.
+    PtrTy(Box<Ty>, bool),
     ArrayTy(Box<Ty>, Option<u64>),
     TupleTy(Vec<Ty>),
     FuncTy(Vec<Ty>, Box<Ty>),
@@ -43,7 +46,7 @@ impl Ty {
     fn kinds(&self) -> EnumSet<Kind> {
         let mut set = EnumSet::empty();
         match *self {
-            StrTy | FuncTy(..) | ArrayTy(..) => {
+            FuncTy(..) | ArrayTy(..) => {
                 set.add(EqKind); // effectively pointer equality
             }
             BoolTy => {
@@ -167,6 +170,41 @@ impl fmt::Show for TyBounds {
 pub struct Typemap {
     pub types: SmallIntMap<Ty>,
     pub bounds: SmallIntMap<TyBounds>,
+    /// The type arguments given (or inferred) at each path that names a
+    /// generic function, keyed by the path's NodeId.
+    pub type_args: SmallIntMap<Vec<Ty>>,
+    // Bounds that have been unified with each other are linked together,
+    // and only the last one in the chain holds their actual bounds.
+    links: SmallIntMap<BoundsId>,
+}
+
+impl Typemap {
+    /// The bounds id that holds the bounds for `bid`.
+    pub fn root(&self, bid: BoundsId) -> BoundsId {
+        match self.links.find(&bid.to_uint()) {
+            Some(&next) => self.root(next),
+            None => bid,
+        }
+    }
+
+    pub fn find_bounds<'a>(&'a self, bid: BoundsId) -> &'a TyBounds {
+        self.bounds.get(&self.root(bid).to_uint())
+    }
+
+    /// Whether the expression with this id is a volatile pointer.
+    pub fn is_volatile_ptr(&self, id: &NodeId) -> bool {
+        let mut ty = self.types.find(&id.to_uint());
+        loop {
+            ty = match ty {
+                Some(&PtrTy(_, volatile)) => return volatile,
+                Some(&BoundTy(bid)) => match *self.find_bounds(bid) {
+                    Concrete(ref t) => Some(t),
+                    _ => return false,
+                },
+                _ => return false,
+            }
+        }
+    }
 }
 
 pub struct Typechecker<'a> {
@@ -176,6 +214,17 @@ pub struct Typechecker<'a> {
     next_bounds_id: uint,
     exits: Vec<Ty>,
     typemap: Typemap,
+    // How many unsafe blocks we're in. Union fields can only be read in one.
+    unsafe_depth: uint,
+    // Whether the expression being checked is being assigned to, so that
+    // if it's a union field, it isn't being read.
+    union_write: bool,
+    // Whether the expression being checked may be an array literal. C only
+    // has those as initializers, so they can only initialize a variable (or
+    // be an element of one that does). A static's initializer is constant,
+    // and a C initializer as a whole, so it may have them anywhere.
+    array_init: bool,
+    in_static: bool,
 }
 
 fn intkind_to_ty(ik: IntKind) -> Ty {
@@ -231,7 +280,59 @@ impl<'a> Typechecker<'a> {
             typemap: Typemap { 
                 types: SmallIntMap::new(),
                 bounds: SmallIntMap::new(),
+                type_args: SmallIntMap::new(),
+                links: SmallIntMap::new(),
+            },
+            unsafe_depth: 0,
+            union_write: false,
+            array_init: false,
+            in_static: false,
+        }
+    }
+
+    // Whether `nid` is a union rather than a struct.
+    fn is_union(&self, nid: NodeId) -> bool {
+        match *self.session.defmap.find(&nid).take_unwrap() {
+            UnionDef(..) => true,
+            _ => false,
+        }
+    }
+
+    // Whether none of the enum's variants have fields, so that it's no more
+    // than its tag, and can be cast to and from an integer.
+    fn is_fieldless_enum(&self, nid: NodeId) -> bool {
+        match *self.session.defmap.find(&nid).take_unwrap() {
+            EnumDef(ref variants, _) => variants.iter().all(|vid| {
+                match *self.session.defmap.find(vid).take_unwrap() {
+                    VariantDef(_, ref args) => args.is_empty(),
+                    _ => fail!("Nonsensical variant id for enum"),
+                }
+            }),
+            _ => false,
+        }
+    }
+
+    // Reading a field of a union reinterprets whatever was stored there
+    // last, so it has to be done in an unsafe block.
+    fn check_union_access(&self, nid: NodeId, write: bool) {
+        if self.is_union(nid) && !write && self.unsafe_depth == 0 {
+            fail!("Reading a field of a union needs an unsafe block");
+        }
+    }
+
+    // `expr` was checked as if it were being assigned to, but its value
+    // turned out to be read instead.
+    fn check_union_read(&mut self, expr: &Expr) {
+        match expr.val {
+            DotExpr(ref e, _) | IndexExpr(ref e, _) => {
+                let ty = self.typemap.types.get(&e.id.to_uint()).clone();
+                match (&expr.val, self.resolve(ty)) {
+                    (&DotExpr(..), StructTy(nid, _)) => self.check_union_access(nid, false),
+                    _ => {}
+                }
+                self.check_union_read(*e);
             }
+            _ => {}
         }
     }
 
@@ -272,11 +373,12 @@ impl<'a> Typechecker<'a> {
     }
 
     fn get_bounds(&self, bid: BoundsId) -> TyBounds {
-        self.typemap.bounds.find(&bid.to_uint()).take_unwrap().clone()
+        self.typemap.find_bounds(bid).clone()
     }
 
     fn update_bounds(&mut self, bid: BoundsId, bounds: TyBounds) {
-        self.typemap.bounds.swap(bid.to_uint(), bounds);
+        let root = self.typemap.root(bid);
+        self.typemap.bounds.swap(root.to_uint(), bounds);
     }
 
     fn add_bound_ty(&mut self, nid: NodeId) -> Ty {
@@ -293,6 +395,17 @@ impl<'a> Typechecker<'a> {
         }
     }
 
+    /// Look through a BoundTy to the concrete type it's bound to, if any.
+    fn resolve(&mut self, t: Ty) -> Ty {
+        match t {
+            BoundTy(bid) => match self.get_bounds(bid) {
+                Concrete(ty) => self.resolve(ty),
+                _ => BoundTy(bid),
+            },
+            t => t,
+        }
+    }
+
     fn generic_to_ty(&mut self, nid: NodeId) -> Ty {
         match self.generics.iter().rev()
                                   .filter_map(|gs| gs.find(&nid))
@@ -302,28 +415,32 @@ impl<'a> Typechecker<'a> {
         }
     }
 
+    // The type named by a path.
+    fn path_to_ty(&mut self, path: &Path) -> Ty {
+        let nid = self.session.resolver.def_from_path(path);
+        match *self.session.defmap.find(&nid).take_unwrap() {
+            StructDef(_, ref tps) | UnionDef(_, ref tps) => {
+                let tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, false);
+                StructTy(nid, tys)
+            }
+            EnumDef(_, ref tps) => {
+                let tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, false);
+                EnumTy(nid, tys)
+            }
+            GenericDef => self.generic_to_ty(nid),
+            TypeDef(ref t) => self.type_to_ty(t),
+            _ => fail!("Expected type name"),
+        }
+    }
+
     fn type_to_ty(&mut self, t: &Type) -> Ty {
         save_ty!(t, match t.val {
             BoolType => BoolTy,
+            StrType => StrTy,
             UnitType => UnitTy,
             IntType(ik) => intkind_to_ty(ik),
-            PtrType(ref t) => PtrTy(box self.type_to_ty(*t)),
-            NamedType(ref path) => {
-                let nid = self.session.resolver.def_from_path(path);
-                match *self.session.defmap.find(&nid).take_unwrap() {
-                    StructDef(_, ref tps) => {
-                        let tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, false);
-                        StructTy(nid, tys)
-                    }
-                    EnumDef(_, ref tps) => {
-                        let tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, false);
-                        EnumTy(nid, tys)
-                    }
-                    GenericDef => self.generic_to_ty(nid),
-                    TypeDef(ref t) => self.type_to_ty(t),
-                    _ => fail!("Expected type name"),
-                }
-            },
+            PtrType(ref t, volatile) => PtrTy(box self.type_to_ty(*t), volatile),
+            NamedType(ref path) => self.path_to_ty(path),
             FuncType(ref args, ref t) => {
                let ret_ty = self.type_to_ty(*t);
                let arg_tys = args.iter().map(|arg| {
@@ -405,7 +522,12 @@ impl<'a> Typechecker<'a> {
                         }
 
                         self.with_generics(gs, |me| {
-                            for (field, fp) in fields.iter().map(|x| x.val1()).zip(fps.iter()) {
+                            for fp in fps.iter() {
+                                let field = match fields.find(&fp.name) {
+                                    Some(field) => field,
+                                    None => fail!("{} has no field {}", path,
+                                                  me.session.interner.name_to_str(&fp.name)),
+                                };
                                 let field_ty = me.type_to_ty(field);
                                 let fp_ty = me.pat_to_ty(&fp.pat);
                                 me.unify(field_ty, fp_ty);
@@ -414,6 +536,7 @@ impl<'a> Typechecker<'a> {
 
                         StructTy(nid, tp_tys)
                     }
+                    UnionDef(..) => fail!("Can't match on a union, as there's no telling which field is in use"),
                     _ => unreachable!(),
                 }
             }
@@ -425,7 +548,7 @@ impl<'a> Typechecker<'a> {
             NumLit(_, ik) => intkind_to_ty(ik),
             StringLit(..) => StrTy,
             BoolLit(..) => BoolTy,
-            NullLit => PtrTy(box BottomTy),
+            NullLit => PtrTy(box BottomTy, false),
         })
     }
 
@@ -440,17 +563,76 @@ impl<'a> Typechecker<'a> {
         FuncTy(arg_tys, box ret_ty)
     }
 
+    /// If `expr` names a variadic extern function, the number of fixed
+    /// arguments it takes.
+    fn variadic_arity(&self, expr: &Expr) -> Option<uint> {
+        match expr.val {
+            PathExpr(ref path) => {
+                let nid = self.session.resolver.def_from_path(path);
+                match self.session.defmap.find(&nid) {
+                    Some(&ExternFuncDef(ref args, _, _, true)) => Some(args.len()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Check that `ty` is something `new` or `new_array` can allocate.
+    fn alloc_ty(&mut self, ty: Ty) -> Ty {
+        match self.resolve(ty.clone()) {
+            ArrayTy(..) => fail!("Arrays are allocated with new_array, not new"),
+            UnitTy => fail!("Cannot allocate a ()"),
+            _ => ty,
+        }
+    }
+
+    fn error(&self, expr: &Expr, msg: &str) -> ! {
+        let file = self.session.parser.filename_of(&expr.id);
+        let pos = self.session.parser.span_of(&expr.id).get_begin();
+        fail!("{}:{}:{}: {}", self.session.interner.name_to_str(&file),
+              pos.row + 1, pos.col + 1, msg)
+    }
+
     fn expr_to_ty(&mut self, expr: &Expr) -> Ty {
+        let union_write = mem::replace(&mut self.union_write, false);
+        let array_init = mem::replace(&mut self.array_init, false);
         save_ty!(expr, match expr.val {
             UnitExpr => UnitTy,
             LitExpr(ref l) => self.lit_to_ty(l),
-            TupleExpr(ref es) => TupleTy(es.iter().map(|e| self.expr_to_ty(e)).collect()),
+            TupleExpr(ref es) => {
+                // The element types may still be refined (a generic int
+                // literal may turn out to be a u8, say), so hand back a
+                // bound rather than the bare TupleTy. That way whatever
+                // we learn later is reflected in this expression's type.
+                let ty = TupleTy(es.iter().map(|e| self.expr_to_ty(e)).collect());
+                let bid = self.add_bounds();
+                self.update_bounds(bid, Concrete(ty));
+                BoundTy(bid)
+            }
+            ArrayExpr(ref es) => {
+                if !array_init && !self.in_static {
+                    self.error(expr, "Array literals can only initialize a variable or a static");
+                }
+                let mut elem_ty = BoundTy(self.add_bounds());
+                for e in es.iter() {
+                    self.array_init = true;
+                    let ty = self.expr_to_ty(e);
+                    elem_ty = self.unify(elem_ty, ty);
+                }
+                ArrayTy(box elem_ty, Some(es.len() as u64))
+            }
             GroupExpr(ref e) => self.expr_to_ty(*e),
             PathExpr(ref path) => {
                 let nid = self.session.resolver.def_from_path(path);
                 match *self.session.defmap.find(&nid).take_unwrap() {
-                    FuncDef(ref args, ref t, ref tps) => {
+                    FuncDef(ref args, ref t, ref tps) |
+                    ExternFuncDef(ref args, ref t, ref tps, _) => {
                         let tp_tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, true);
+                        if tps.len() > 0 {
+                            self.typemap.type_args.insert(path.id.to_uint(), tp_tys.clone());
+                        }
+
                         let mut gs = TreeMap::new();
                         for (tp, tp_ty) in tps.iter().zip(tp_tys.iter()) {
                             gs.insert(*tp, tp_ty.clone());
@@ -494,13 +676,19 @@ impl<'a> Typechecker<'a> {
                     _ => fail!("{} does not name a value", path),
                 }
             }
-            StructExpr(ref path, ref flds) => {
+            StructExpr(ref path, ref flds, ref base) => {
                 let nid = self.session.resolver.def_from_path(path);
                 let (fields, tps) = match *self.session.defmap.find(&nid).take_unwrap() {
-                    StructDef(ref fields, ref tps) => (fields, tps),
+                    StructDef(ref fields, ref tps) | UnionDef(ref fields, ref tps) => (fields, tps),
                     _ => fail!("{} does not name a struct", path),
                 };
 
+                // A union is built from just one of its fields.
+                let is_union = self.is_union(nid);
+                if is_union && (flds.len() != 1 || base.is_some()) {
+                    fail!("A union literal gives exactly one field, in {}", path);
+                }
+
                 let tp_tys = self.tps_to_tys(tps, &path.val.elems.last().unwrap().val.tps, true);
 
                 let mut gs = TreeMap::new();
@@ -508,15 +696,44 @@ impl<'a> Typechecker<'a> {
                     gs.insert(*tp, tp_ty.clone());
                 }
 
+                let ty = StructTy(nid, tp_tys);
+
                 self.with_generics(gs, |me| {
-                    for (field, fld) in fields.iter().map(|x| x.val1()).zip(flds.iter()) {
+                    let mut seen = TreeSet::new();
+                    for &(ref name, ref fld) in flds.iter() {
+                        let field = match fields.find(name) {
+                            Some(field) => field,
+                            None => fail!("{} has no field {}", path,
+                                          me.session.interner.name_to_str(name)),
+                        };
+                        if !seen.insert(*name) {
+                            fail!("Field {} specified more than once",
+                                  me.session.interner.name_to_str(name));
+                        }
                         let field_ty = me.type_to_ty(field);
-                        let fld_ty = me.expr_to_ty(fld.ref1());
+                        let fld_ty = me.expr_to_ty(fld);
                         me.unify(field_ty, fld_ty);
                     }
+
+                    match *base {
+                        // The base supplies every field not given explicitly.
+                        Some(ref base) => {
+                            let base_ty = me.expr_to_ty(*base);
+                            me.unify(ty.clone(), base_ty);
+                        }
+                        None if is_union => {}
+                        None => {
+                            for name in fields.keys() {
+                                if !seen.contains(name) {
+                                    fail!("Missing field {} in {}",
+                                          me.session.interner.name_to_str(name), path);
+                                }
+                            }
+                        }
+                    }
                 });
 
-                StructTy(nid, tp_tys)
+                ty
             }
             BinOpExpr(ref op, ref l, ref r) => {
                 let l_ty = self.expr_to_ty(*l);
@@ -530,9 +747,9 @@ impl<'a> Typechecker<'a> {
                     Negate => self.check_ty_bounds(ty, Constrained(enumset!(SubKind))),
                     BitNot => self.check_ty_bounds(ty, Constrained(enumset!(BitXorKind))),
                     LogNot => self.check_ty_bounds(ty, Concrete(BoolTy)),
-                    AddrOf => PtrTy(box ty),
+                    AddrOf => PtrTy(box ty, false),
                     Deref => match ty {
-                        PtrTy(p_ty) => *p_ty,
+                        PtrTy(p_ty, _) => *p_ty,
                         _ => unreachable!(),
                     }
                 };
@@ -540,13 +757,20 @@ impl<'a> Typechecker<'a> {
                 expr_ty
             }
             IndexExpr(ref a, ref i) => {
+                // Assigning to an element of an array assigns to the
+                // array, but indexing a pointer reads the pointer.
+                self.union_write = union_write;
                 let a_ty = self.expr_to_ty(*a);
+                match a_ty {
+                    PtrTy(..) if union_write => self.check_union_read(*a),
+                    _ => {}
+                }
                 let i_ty = self.expr_to_ty(*i);
 
                 self.check_ty_bounds(i_ty, Concrete(UintTy(AnyWidth)));
 
                 match a_ty {
-                    ArrayTy(ty, _) | PtrTy(ty) => *ty,
+                    ArrayTy(ty, _) | PtrTy(ty, _) => *ty,
                     _ => unreachable!(),
                 }
             }
@@ -558,7 +782,33 @@ impl<'a> Typechecker<'a> {
                 self.unify(tb_ty, fb_ty)
             }
             CallExpr(ref e, ref args) => {
-                let arg_tys = args.iter().map(|arg| self.expr_to_ty(arg)).collect();
+                let mut arg_tys: Vec<Ty> = args.iter().map(|arg| self.expr_to_ty(arg)).collect();
+                // Only the fixed arguments of a variadic extern have types to
+                // check against; the rest are passed along as they are.
+                match self.variadic_arity(*e) {
+                    Some(n) => {
+                        if arg_tys.len() < n {
+                            fail!("{} takes at least {} arguments, but was given {}",
+                                  e, n, arg_tys.len());
+                        }
+                        arg_tys.truncate(n);
+                        // C only promotes them, so an integer whose type
+                        // nothing else settles is passed as an i32, the
+                        // way C would pass an int.
+                        for arg in args.iter().skip(n) {
+                            let ty = self.typemap.types.get(&arg.id.to_uint()).clone();
+                            match self.resolve(ty.clone()) {
+                                GenericIntTy => { save_ty!(arg, IntTy(Width32)); }
+                                BoundTy(bid) => match self.get_bounds(bid) {
+                                    Constrained(..) => { self.unify(ty, IntTy(Width32)); }
+                                    _ => {}
+                                },
+                                _ => {}
+                            }
+                        }
+                    }
+                    None => {}
+                }
                 let e_ty = self.expr_to_ty(*e);
                 match self.unify(FuncTy(arg_tys, box BottomTy), e_ty) {
                     FuncTy(_, ret_ty) => *ret_ty,
@@ -568,6 +818,12 @@ impl<'a> Typechecker<'a> {
             BlockExpr(ref b) => {
                 self.block_to_ty(*b)
             }
+            UnsafeExpr(ref b) => {
+                self.unsafe_depth += 1;
+                let ty = self.block_to_ty(*b);
+                self.unsafe_depth -= 1;
+                ty
+            }
             ReturnExpr(ref e) => {
                 let ty = self.expr_to_ty(*e);
                 self.exits.push(ty);
@@ -575,23 +831,146 @@ impl<'a> Typechecker<'a> {
             }
             BreakExpr => BottomTy,
             ContinueExpr => BottomTy,
+            AssertExpr(ref e, ref msg, _) => {
+                let e_ty = self.expr_to_ty(*e);
+                self.unify(BoolTy, e_ty);
+                for msg in msg.iter() {
+                    let msg_ty = self.expr_to_ty(*msg);
+                    self.unify(StrTy, msg_ty);
+                }
+                UnitTy
+            }
+            PanicExpr(ref msg) => {
+                let msg_ty = self.expr_to_ty(*msg);
+                self.unify(StrTy, msg_ty);
+                BottomTy
+            }
+            UnreachableExpr => BottomTy,
+            AtomicExpr(FenceOp, _, _) => UnitTy,
+            AtomicExpr(op, ref es, _) => {
+                let p_ty = self.expr_to_ty(es.get(0));
+                let elem_ty = match self.unify(BottomTy, p_ty) {
+                    PtrTy(t, _) => *t,
+                    t => fail!("Expected a pointer for {}, but found {}", op, t),
+                };
+
+                // Only integers and pointers are atomic, and only integers
+                // (the only types that can be multiplied) can be added to.
+                let kinds = match op {
+                    FetchAddOp => enumset!(CmpKind, MulKind),
+                    _ => enumset!(CmpKind),
+                };
+                let elem_ty = self.check_ty_bounds(elem_ty, Constrained(kinds));
+                for e in es.iter().skip(1) {
+                    let ty = self.expr_to_ty(e);
+                    self.unify(elem_ty.clone(), ty);
+                }
+
+                match op {
+                    StoreOp => UnitTy,
+                    _ => elem_ty,
+                }
+            }
+            AsmExpr(_, ref operands, _) => {
+                for operand in operands.iter() {
+                    match (&operand.kind, &operand.expr.val) {
+                        (&AsmIn, _) |
+                        (_, &PathExpr(..)) | (_, &UnOpExpr(WithId { val: Deref, .. }, _)) |
+                        (_, &IndexExpr(..)) | (_, &DotExpr(..)) | (_, &TupleIndexExpr(..)) |
+                        (_, &ArrowExpr(..)) => {}
+                        _ => fail!("Output of asm! is not an lvalue"),
+                    }
+
+                    // Only integers and pointers go in registers.
+                    let ty = self.expr_to_ty(&operand.expr);
+                    self.check_ty_bounds(ty, Constrained(enumset!(CmpKind)));
+                }
+                UnitTy
+            }
+            SizeofExpr(ref t) => {
+                self.type_to_ty(t);
+                UintTy(Width32)
+            }
+            OffsetofExpr(ref t, ref fld) => {
+                let nid = match self.type_to_ty(t) {
+                    StructTy(nid, _) => nid,
+                    ty => fail!("offsetof needs a structure, got {}", ty),
+                };
+
+                match *self.session.defmap.find(&nid).take_unwrap() {
+                    StructDef(ref fields, _) | UnionDef(ref fields, _) => {
+                        if fields.find(fld).is_none() {
+                            fail!("Structure has no field {}",
+                                  self.session.interner.name_to_str(fld));
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+                UintTy(Width32)
+            }
+            NewExpr(ref e) => {
+                let ty = match e.val {
+                    PathExpr(ref path) if self.session.resolver.is_new_type(path) => {
+                        self.path_to_ty(path)
+                    }
+                    _ => self.expr_to_ty(*e),
+                };
+                PtrTy(box self.alloc_ty(ty), false)
+            }
+            NewTypeExpr(ref t) => {
+                let ty = self.type_to_ty(t);
+                PtrTy(box self.alloc_ty(ty), false)
+            }
+            NewArrayExpr(ref t, ref n) => {
+                let ty = self.type_to_ty(t);
+                // The count is a u32, as the run time takes it.
+                let n_ty = self.expr_to_ty(*n);
+                self.check_ty_bounds(n_ty, Concrete(UintTy(Width32)));
+                PtrTy(box self.alloc_ty(ty), false)
+            }
+            FreeExpr(ref e) => {
+                let ty = self.expr_to_ty(*e);
+                match self.resolve(ty) {
+                    PtrTy(..) => {}
+                    ty => fail!("free needs a pointer, got {}", ty),
+                }
+                UnitTy
+            }
             CastExpr(ref e, ref t) => {
                 let e_ty = self.expr_to_ty(*e);
                 let t_ty = self.type_to_ty(t);
 
                 match e_ty {
+                    // A string can be cast to a pointer to its bytes, for
+                    // handing to C.
+                    StrTy => match t_ty {
+                        PtrTy(ref p, _) if **p == UintTy(Width8) => {}
+                        _ => fail!("Strings can only be cast to *u8"),
+                    },
+                    // A field-less enum can be cast to its tag.
+                    EnumTy(nid, _) if self.is_fieldless_enum(nid) => match t_ty {
+                        GenericIntTy | UintTy(..) | IntTy(..) => {}
+                        _ => fail!("Enums can only be cast to integers"),
+                    },
                     GenericIntTy | UintTy(..) | IntTy(..) | PtrTy(..) => {}
                     _ => fail!("Cannot cast expression of non-integral type"),
                 }
 
                 match t_ty {
                     GenericIntTy | UintTy(..) | IntTy(..) | PtrTy(..) => t_ty,
+                    // And back, which is checked when the program runs.
+                    EnumTy(nid, _) if self.is_fieldless_enum(nid) => match e_ty {
+                        GenericIntTy | UintTy(..) | IntTy(..) => t_ty,
+                        _ => fail!("Only integers can be cast to enums"),
+                    },
                     _ => fail!("Cannot cast to non-integral type"),
                 }
             }
             AssignExpr(ref op, ref lv, ref rv) => {
                 let l_ty = match lv.val {
-                    PathExpr(..) | UnOpExpr(WithId { val: Deref, .. }, _) | IndexExpr(..) | DotExpr(..) | ArrowExpr(..) => {
+                    PathExpr(..) | UnOpExpr(WithId { val: Deref, .. }, _) | IndexExpr(..) | DotExpr(..) | TupleIndexExpr(..) | ArrowExpr(..) => {
+                        // A compound assignment reads the old value first.
+                        self.union_write = op.is_none();
                         self.expr_to_ty(*lv)
                     }
                     _ => fail!("LHS of assignment is not an lvalue"),
@@ -605,14 +984,17 @@ impl<'a> Typechecker<'a> {
                 }
             }
             DotExpr(ref e, ref fld) => {
+                // Assigning to a field of a field assigns to both.
+                self.union_write = union_write;
                 let e_ty = self.expr_to_ty(*e);
                 let (nid, tp_tys) = match self.unify(BottomTy, e_ty) {
                     StructTy(nid, tp_tys) => (nid, tp_tys),
                     ty => fail!("Expression is not a structure, got {}", ty),
                 };
+                self.check_union_access(nid, union_write);
 
                 match *self.session.defmap.find(&nid).take_unwrap() {
-                    StructDef(ref fields, ref tps) => {
+                    StructDef(ref fields, ref tps) | UnionDef(ref fields, ref tps) => {
                         let mut gs = TreeMap::new();
                         for (tp, tp_ty) in tps.iter().zip(tp_tys.iter()) {
                             gs.insert(*tp, tp_ty.clone());
@@ -624,15 +1006,28 @@ impl<'a> Typechecker<'a> {
                     _ => unreachable!(),
                 }
             }
+            TupleIndexExpr(ref e, idx) => {
+                let e_ty = self.expr_to_ty(*e);
+                match self.resolve(e_ty) {
+                    TupleTy(tys) => {
+                        if idx as uint >= tys.len() {
+                            fail!("Tuple has {} elements, but tried to get element {}", tys.len(), idx);
+                        }
+                        tys.get(idx as uint).clone()
+                    }
+                    ty => fail!("Expression is not a tuple, got {}", ty),
+                }
+            }
             ArrowExpr(ref e, ref fld) => {
                 let e_ty = self.expr_to_ty(*e);
                 let (nid, tp_tys) = match self.unify(BottomTy, e_ty) {
-                    PtrTy(box StructTy(nid, tp_tys)) => (nid, tp_tys),
+                    PtrTy(box StructTy(nid, tp_tys), _) => (nid, tp_tys),
                     _ => fail!("Expression is not a pointer to a structure"),
                 };
+                self.check_union_access(nid, union_write);
 
                 match *self.session.defmap.find(&nid).take_unwrap() {
-                    StructDef(ref fields, ref tps) => {
+                    StructDef(ref fields, ref tps) | UnionDef(ref fields, ref tps) => {
                         let mut gs = TreeMap::new();
                         for (tp, tp_ty) in tps.iter().zip(tp_tys.iter()) {
                             gs.insert(*tp, tp_ty.clone());
@@ -750,15 +1145,21 @@ impl<'a> Typechecker<'a> {
         match (t1, t2) {
             (BottomTy, t) | (t, BottomTy) => t,
             (BoundTy(b1), BoundTy(b2)) => {
+                let (r1, r2) = (self.typemap.root(b1), self.typemap.root(b2));
                 let bounds =
-                    if b1 == b2 {
+                    if r1 == r2 {
                         self.get_bounds(b1)
                     } else {
                         let bs1 = self.get_bounds(b1);
                         let bs2 = self.get_bounds(b2);
                         let bounds = self.merge_bounds(bs1, bs2);
                         self.update_bounds(b1, bounds.clone());
-                        self.update_bounds(b2, bounds.clone());
+                        // From now on b2 is whatever b1 turns out to be,
+                        // even if that isn't known yet.
+                        let r2 = self.typemap.root(b2);
+                        if self.typemap.root(b1) != r2 {
+                            self.typemap.links.insert(r2.to_uint(), b1);
+                        }
                         bounds
                     };
 
@@ -795,8 +1196,17 @@ impl<'a> Typechecker<'a> {
 
                 ctor(w)
             },
-            (PtrTy(p1), PtrTy(p2)) =>
-                PtrTy(box self.unify(*p1, *p2)),
+            (PtrTy(p1, v1), PtrTy(p2, v2)) => {
+                // Null is either. Otherwise a pointer only becomes (or
+                // stops being) volatile with a cast.
+                let v = match (&*p1, &*p2) {
+                    (&BottomTy, _) => v2,
+                    (_, &BottomTy) => v1,
+                    _ if v1 == v2 => v1,
+                    _ => self.mismatch(&PtrTy(p1.clone(), v1), &PtrTy(p2.clone(), v2)),
+                };
+                PtrTy(box self.unify(*p1, *p2), v)
+            },
             (ArrayTy(a1, l1), ArrayTy(a2, l2)) => {
                 let l = match (l1, l2) {
                     (None, l) | (l, None) => l,
@@ -872,6 +1282,7 @@ impl<'a> Visitor for Typechecker<'a> {
 
                 match *e {
                     Some(ref e) => {
+                        self.array_init = true;
                         let e_ty = self.expr_to_ty(e);
                         self.unify(ty, e_ty);
                     }
@@ -896,6 +1307,9 @@ impl<'a> Visitor for Typechecker<'a> {
                 let mut gs = TreeMap::new();
                 for (tp, tp_ty) in tps.iter().zip(tp_tys.iter()) {
                     gs.insert(tp.id, tp_ty.clone());
+                    // The C target looks these up to substitute concrete
+                    // types into the body.
+                    self.typemap.types.insert(tp.id.to_uint(), tp_ty.clone());
                 }
 
                 self.with_generics(gs, |me| {
@@ -918,16 +1332,20 @@ impl<'a> Visitor for Typechecker<'a> {
                     Some(ref t) => self.type_to_ty(t),
                     None => self.add_bound_ty(ident.id),
                 };
-                
+                // The C target declares the static with this type.
+                self.typemap.types.insert(ident.id.to_uint(), ty.clone());
+
                 match *e {
                     Some(ref e) => {
+                        self.in_static = true;
                         let e_ty = self.expr_to_ty(e);
+                        self.in_static = false;
                         self.unify(ty, e_ty);
                     }
                     None => {}
                 }
             }
-            StructItem(..) | EnumItem(..) => {}
+            StructItem(..) | UnionItem(..) | EnumItem(..) | ExternFuncItem(..) => {}
         }
     }
 }
@@ -954,8 +1372,18 @@ fn lol<U>(mad: U) -> U {
     mad
 }
 
+extern fn printf(fmt: *u8, ...) -> i32;
+
 fn wot(t: u32, flags: bool[3]) -> Option<bool> {
     let s = (t + 42) > 100 && false || flags[0];
+    printf(null, t, s);
+    let n = 0u32;
+    let old = atomic_cas(&n, 0, t, seq_cst) + atomic_fetch_add(&n, 1, relaxed);
+    atomic_store(&n, atomic_load(&n, acquire), release);
+    atomic_fence(seq_cst);
+    let reg = 4096 as *volatile u32;
+    *reg = *reg | t;
+    reg = null;
     while true {
         let i;
         for ((); s && i < 10u; ()) {
@@ -976,6 +1404,364 @@ fn test_match<T>(flags: bool[3], o: Option<T>, f: fn(T) -> ()) -> Option<bool> {
 ");
 
 
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn volatile_mismatch() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn f(p: *u32) -> *volatile u32 {
+    p
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn atomic_bool() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn f(p: *bool) -> bool {
+    atomic_load(p, seq_cst)
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn variadic_arg_is_i32() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+extern fn printf(fmt: *u8, ...) -> i32;
+
+fn f() -> u8 {
+    let n = 5;
+    printf(null, n);
+    n
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn array_literal_inits() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+struct grid { rows: u32[2][2], n: u32 }
+
+static g: grid = grid { rows: [[1, 2], [3, 4]], n: 2 };
+static pairs: (u32[2], bool) = ([5, 6], true);
+
+fn f() -> u32 {
+    let a: u32[2][2] = [[1, 2], [3, 4]];
+    let b = [7u32, 8];
+    a[1][0] + b[1]
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn array_literal_as_arg() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn first(xs: u32[2]) -> u32 { xs[0] }
+
+fn f() -> u32 {
+    first([1, 2])
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn array_literal_assigned() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn f() {
+    let a: u32[2];
+    a = [1, 2];
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn asm_tyck_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r#"
+fn f(p: *u8) -> u32 {
+    let n = 1u32;
+    asm!("add {1}, {0}", inout(reg) n, in(reg) p as u32, clobber("cc"));
+    asm!("mov {1}, {0}", out("m") *p, in(reg) 0u8);
+    n
+}
+"#);
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn asm_struct_operand() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r#"
+struct pair {
+    a: u32,
+    b: u32,
+}
+
+fn f(p: pair) {
+    asm!("nop", in(reg) p);
+}
+"#);
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn sizeof_tyck_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+#[repr(C)]
+#[packed]
+struct header {
+    kind: u8,
+    len: u32,
+}
+
+fn f() -> u32 {
+    sizeof(header) + offsetof(header, len) + sizeof(*u8[4])
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn offsetof_missing_field() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+struct header {
+    kind: u8,
+}
+
+fn f() -> u32 {
+    offsetof(header, len)
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn alloc_tyck_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+struct ll {
+    data: i32,
+    next: *ll,
+}
+
+fn boxed<T>(t: T) -> *T {
+    new(t)
+}
+
+fn f(n: u32) -> *ll {
+    let a: *u32 = new(u32);
+    let b: *ll = new(ll { data: 1, next: null });
+    let c: **u8 = new_array(*u8, n + 1);
+    let d: *bool = boxed(true);
+    free(a);
+    free(c);
+    free(d);
+    b->next = new(ll);
+    b
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn new_array_with_new() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn f() -> *u32 {
+    new(u32[4])
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn free_non_pointer() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+fn f() {
+    free(5);
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn union_tyck_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+union bits {
+    word: u32,
+    bytes: u8[4],
+}
+
+struct holder {
+    b: bits,
+}
+
+fn f(p: *bits) -> u32 {
+    let b = bits { word: 1 };
+    b.word = 2;
+    b.bytes[0] = 1;
+    let h = holder { b: b };
+    h.b.bytes[1] = 2;
+    h.b.word = 3;
+    p->word = 4;
+    let byte = unsafe { b.bytes[0] };
+    unsafe { h.b.word + p->word + byte as u32 + sizeof(bits) }
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn union_read_outside_unsafe() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+union bits {
+    word: u32,
+    bytes: u8[4],
+}
+
+fn f() -> u32 {
+    let b = bits { word: 1 };
+    b.word += 1;
+    0
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn union_write_through_ptr_field() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+union ptrs {
+    word: u32,
+    bytes: *u8,
+}
+
+fn f(p: ptrs) {
+    p.bytes[0] = 1;
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn union_literal_two_fields() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+union bits {
+    word: u32,
+    half: u16,
+}
+
+fn f() {
+    let b = bits { word: 1, half: 2 };
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    fn enum_cast_test() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+#[repr(u8)]
+enum Cmd {
+    Read = 0x10,
+    Write = 0x20,
+    Sync,
+}
+
+fn f(n: u32) -> u32 {
+    let c = n as Cmd;
+    let b: u8 = Read as u8;
+    c as u32 + b as u32
+}
+");
+
+        let mut tyck = Typechecker::new(&session);
+        tyck.visit_module(&tree);
+    }
+
+    #[test]
+    #[should_fail]
+    fn enum_with_fields_cast() {
+        let mut session = Session::new();
+        let tree = session.parse_str(r"
+enum Opt {
+    Some(u32),
+    None,
+}
+
+fn f() -> u32 {
+    None as u32
+}
+");
+
         let mut tyck = Typechecker::new(&session);
         tyck.visit_module(&tree);
     }
@@ -1,5 +1,6 @@
 use std::collections::TreeMap;
 use std::fmt::{Show, Formatter};
+use std::from_str::FromStr;
 
 use std::fmt;
 
@@ -43,3 +44,33 @@ impl Show for IntKind {
         }
     }
 }
+
+// The inverse of the above, for the sized kinds, so that attributes like
+// `#[repr(u8)]` can name one.
+impl FromStr for IntKind {
+    fn from_str(s: &str) -> Option<IntKind> {
+        let w = match s.slice_from(if s.len() > 0 { 1 } else { 0 }) {
+            "32" => Width32,
+            "16" => Width16,
+            "8"  => Width8,
+            _ => return None,
+        };
+        match s.char_at(0) {
+            'u' => Some(UnsignedInt(w)),
+            'i' => Some(SignedInt(w)),
+            _ => None,
+        }
+    }
+}
+
+/// What happens when integer arithmetic overflows, divides by zero, or
+/// shifts by at least the width of its type. The rules are set out in full
+/// under "Integer arithmetic" in README.md.
+#[deriving(Eq, PartialEq, Clone, Show)]
+pub enum OverflowMode {
+    /// Results wrap around. Dividing by zero and over-wide shifts are
+    /// still undefined.
+    Wrapping,
+    /// The program traps, reporting where it happened.
+    Checked,
+}
@@ -1,13 +1,122 @@
 use ast::{BoolLit, LitNode, NumLit};
-use util::{IntKind, Width};
+use ast::{BinOpNode, PlusOp, MinusOp, TimesOp, DivideOp, ModOp};
+use ast::{BitAndOp, BitOrOp, BitXorOp, LeftShiftOp, RightShiftOp};
+use util::{IntKind, Width, GenericInt, SignedInt, UnsignedInt};
+use util::{AnyWidth, Width32, Width16, Width8};
+use util::{OverflowMode, Wrapping, Checked};
 
-fn num_op_helper(kind1: &IntKind, rhs: &LitNode, f: |u64| -> u64) -> LitNode {
-    match *rhs {
-        NumLit(n2, kind2) => {
-            assert_eq!(*kind1, kind2);
-            NumLit(f(n2), kind2)
+// Integer literals hold the bits of their value, zero extended to 64 bits.
+// Unsuffixed ints, and ints whose width was never given, are 32 bits, the
+// same as in the C target.
+
+fn kind_bits(kind: &IntKind) -> uint {
+    match *kind {
+        GenericInt | SignedInt(AnyWidth) | UnsignedInt(AnyWidth) => 32,
+        SignedInt(w) | UnsignedInt(w) => match w {
+            Width32 | AnyWidth => 32,
+            Width16 => 16,
+            Width8 => 8,
         },
-        _ => fail!("Incompatible types.")
+    }
+}
+
+fn kind_signed(kind: &IntKind) -> bool {
+    match *kind {
+        GenericInt | SignedInt(..) => true,
+        UnsignedInt(..) => false,
+    }
+}
+
+// The bits of `n`, truncated to the width of `kind`.
+fn truncate(n: u64, kind: &IntKind) -> u64 {
+    n & ((1u64 << kind_bits(kind)) - 1)
+}
+
+// The value that the bits `n` represent in an integer of type `kind`.
+fn to_i64(n: u64, kind: &IntKind) -> i64 {
+    let bits = kind_bits(kind);
+    let n = truncate(n, kind);
+    if kind_signed(kind) && n >> (bits - 1) != 0 {
+        (n | !((1u64 << bits) - 1)) as i64
+    } else {
+        n as i64
+    }
+}
+
+fn in_range(v: i64, kind: &IntKind) -> bool {
+    let bits = kind_bits(kind);
+    if kind_signed(kind) {
+        v >= -(1i64 << (bits - 1)) && v < (1i64 << (bits - 1))
+    } else {
+        v >= 0 && v < (1i64 << bits)
+    }
+}
+
+// The exact result of an integer operation, which always fits in an i64
+// since the operands are at most 32 bits. None if the operation is
+// undefined.
+fn exact_int_op(op: &BinOpNode, n1: u64, n2: u64, kind: &IntKind) -> Option<i64> {
+    let (a, b) = (to_i64(n1, kind), to_i64(n2, kind));
+    let bits = kind_bits(kind) as i64;
+    match *op {
+        PlusOp => Some(a + b),
+        MinusOp => Some(a - b),
+        TimesOp => Some(a * b),
+        DivideOp if b != 0 => Some(a / b),
+        ModOp if b != 0 => Some(a % b),
+        BitAndOp => Some(to_i64(n1 & n2, kind)),
+        BitOrOp => Some(to_i64(n1 | n2, kind)),
+        BitXorOp => Some(to_i64(n1 ^ n2, kind)),
+        // Only the shift amount is checked; bits shifted out are lost.
+        LeftShiftOp if b >= 0 && b < bits => {
+            Some(to_i64(truncate(truncate(n1, kind) << b as uint, kind), kind))
+        }
+        RightShiftOp if b >= 0 && b < bits => Some(a >> b as uint),
+        _ => None,
+    }
+}
+
+/// Apply an integer operator to two literals of the same type. Gives None
+/// if the operation is undefined (dividing by zero, or shifting by at least
+/// the width of the type), or if it overflows and `mode` is Checked.
+pub fn int_op(op: &BinOpNode, lhs: &LitNode, rhs: &LitNode,
+              mode: OverflowMode) -> Option<LitNode> {
+    match (lhs, rhs) {
+        (&NumLit(n1, kind1), &NumLit(n2, kind2)) => {
+            assert_eq!(kind1, kind2);
+            exact_int_op(op, n1, n2, &kind1).and_then(|v| {
+                if mode == Checked && !in_range(v, &kind1) {
+                    None
+                } else {
+                    Some(NumLit(truncate(v as u64, &kind1), kind1))
+                }
+            })
+        }
+        _ => fail!("Incompatible types."),
+    }
+}
+
+/// The value of an integer literal, taking the signedness of its type into
+/// account.
+pub fn int_value(lit: &LitNode) -> i64 {
+    match *lit {
+        NumLit(n, kind) => to_i64(n, &kind),
+        _ => fail!("Not an integer: {}", lit),
+    }
+}
+
+/// Convert an integer literal to another integer type, the way that an
+/// `as` cast does: sign extending it if it was signed, and truncating it to
+/// the new width.
+pub fn int_cast(lit: &LitNode, kind: IntKind) -> LitNode {
+    NumLit(truncate(int_value(lit) as u64, &kind), kind)
+}
+
+// Wrapping arithmetic, for the operator overloads.
+fn wrapping_op(op: BinOpNode, lhs: &LitNode, rhs: &LitNode) -> LitNode {
+    match int_op(&op, lhs, rhs, Wrapping) {
+        Some(lit) => lit,
+        None => fail!("Undefined operation: {} {} {}", lhs, op, rhs),
     }
 }
 
@@ -24,7 +133,13 @@ pub fn generic_op(lhs: &LitNode, rhs: &LitNode,
               intfunc: |u64, u64| -> u64,
               boolfunc: |bool, bool| -> bool) -> LitNode {
     match *lhs {
-        NumLit(n1, kind1) => num_op_helper(&kind1, rhs, |x| intfunc(n1, x)),
+        NumLit(n1, kind1) => match *rhs {
+            NumLit(n2, kind2) => {
+                assert_eq!(kind1, kind2);
+                NumLit(truncate(intfunc(n1, n2), &kind1), kind1)
+            }
+            _ => fail!("Incompatible types."),
+        },
         BoolLit(b) => bool_op_helper(rhs, |x| boolfunc(b, x)),
         _ => fail!("Unimplemented.")
     }
@@ -32,11 +147,11 @@ pub fn generic_op(lhs: &LitNode, rhs: &LitNode,
 
 /// An operator that takes ints and returns a bool.
 pub fn relation_op(lhs: &LitNode, rhs: &LitNode,
-                   f: |u64, u64| -> bool) -> LitNode {
+                   f: |i64, i64| -> bool) -> LitNode {
     match *lhs {
         NumLit(n1, kind1) => match *rhs {
             NumLit(n2, kind2) if kind1 == kind2 => {
-                BoolLit(f(n1, n2))
+                BoolLit(f(to_i64(n1, &kind1), to_i64(n2, &kind2)))
             },
             _ => fail!(),
         },
@@ -46,48 +161,98 @@ pub fn relation_op(lhs: &LitNode, rhs: &LitNode,
 
 impl Add<LitNode, LitNode> for LitNode {
     fn add(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x+y, |_,_| fail!())
+        wrapping_op(PlusOp, self, rhs)
     }
 }
 
 impl Mul<LitNode, LitNode> for LitNode {
     fn mul(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x*y, |_,_| fail!())
+        wrapping_op(TimesOp, self, rhs)
     }
 }
 
 impl Sub<LitNode, LitNode> for LitNode {
     fn sub(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x-y, |_,_| fail!())
+        wrapping_op(MinusOp, self, rhs)
     }
 }
 
 impl Div<LitNode, LitNode> for LitNode {
     fn div(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x/y, |_,_| fail!())
+        wrapping_op(DivideOp, self, rhs)
     }
 }
 
 impl BitAnd<LitNode, LitNode> for LitNode {
     fn bitand(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x&y, |_,_| fail!())
+        wrapping_op(BitAndOp, self, rhs)
     }
 }
 
 impl BitOr<LitNode, LitNode> for LitNode {
     fn bitor(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x|y, |_,_| fail!())
+        wrapping_op(BitOrOp, self, rhs)
     }
 }
 
 impl BitXor<LitNode, LitNode> for LitNode {
     fn bitxor(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x^y, |_,_| fail!())
+        wrapping_op(BitXorOp, self, rhs)
     }
 }
 
 impl Rem<LitNode, LitNode> for LitNode {
     fn rem(&self, rhs: &LitNode) -> LitNode {
-        generic_op(self, rhs, |x, y| x%y, |_,_| fail!())
+        wrapping_op(ModOp, self, rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{int_op, int_cast, int_value};
+    use ast::{NumLit, PlusOp, MinusOp, TimesOp, DivideOp, ModOp, LeftShiftOp, RightShiftOp};
+    use util::{SignedInt, UnsignedInt, Width8, Width16, Width32, Wrapping, Checked};
+
+    #[test]
+    fn wrapping() {
+        let ubyte = UnsignedInt(Width8);
+        let sbyte = SignedInt(Width8);
+        assert_eq!(int_op(&PlusOp, &NumLit(200, ubyte), &NumLit(100, ubyte), Wrapping),
+                   Some(NumLit(44, ubyte)));
+        assert_eq!(int_op(&MinusOp, &NumLit(0, ubyte), &NumLit(1, ubyte), Wrapping),
+                   Some(NumLit(255, ubyte)));
+        assert_eq!(int_op(&PlusOp, &NumLit(127, sbyte), &NumLit(1, sbyte), Wrapping),
+                   Some(NumLit(0x80, sbyte)));
+        // -128 >> 1 is an arithmetic shift.
+        assert_eq!(int_op(&RightShiftOp, &NumLit(0x80, sbyte), &NumLit(1, sbyte), Wrapping),
+                   Some(NumLit(0xc0, sbyte)));
+    }
+
+    #[test]
+    fn checked() {
+        let uword = UnsignedInt(Width32);
+        let sword = SignedInt(Width32);
+        assert_eq!(int_op(&TimesOp, &NumLit(0x10000, uword), &NumLit(0x10000, uword), Checked),
+                   None);
+        assert_eq!(int_op(&TimesOp, &NumLit(0x10000, uword), &NumLit(0xffff, uword), Checked),
+                   Some(NumLit(0xffff0000, uword)));
+        assert_eq!(int_op(&DivideOp, &NumLit(0x80000000, sword), &NumLit(0xffffffff, sword), Checked),
+                   None);
+        // Wrapping, the quotient is what the C target works out too.
+        assert_eq!(int_op(&DivideOp, &NumLit(0x80000000, sword), &NumLit(0xffffffff, sword), Wrapping),
+                   Some(NumLit(0x80000000, sword)));
+        assert_eq!(int_op(&ModOp, &NumLit(0x80000000, sword), &NumLit(0xffffffff, sword), Wrapping),
+                   Some(NumLit(0, sword)));
+        assert_eq!(int_op(&DivideOp, &NumLit(1, sword), &NumLit(0, sword), Wrapping), None);
+        assert_eq!(int_op(&LeftShiftOp, &NumLit(1, uword), &NumLit(32, uword), Wrapping), None);
+    }
+
+    #[test]
+    fn casts() {
+        let sbyte = SignedInt(Width8);
+        let uhalf = UnsignedInt(Width16);
+        assert_eq!(int_value(&NumLit(0xff, sbyte)), -1);
+        assert_eq!(int_cast(&NumLit(0xff, sbyte), uhalf), NumLit(0xffff, uhalf));
+        assert_eq!(int_cast(&NumLit(0x1234, uhalf), sbyte), NumLit(0x34, sbyte));
     }
 }
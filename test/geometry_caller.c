@@ -0,0 +1,8 @@
+/* A C program that uses geometry.mc through its header, and is linked
+ * with it. */
+#include "geometry.h"
+
+int main(void) {
+    shape s = make_square(3);
+    return area(&s) == 9 ? 0 : 1;
+}